@@ -0,0 +1,163 @@
+use std::io::IsTerminal;
+
+use crate::config::Config;
+use crate::error::{Result, SnapError};
+
+/// The kinds of coloured output; each can be set in config under its key
+#[derive(Clone, Copy)]
+pub enum Slot {
+    Meta,
+    Frag,
+    Old,
+    New,
+    Whitespace,
+    Commit,
+    StatusAdded,
+    StatusChanged,
+    StatusUntracked,
+}
+
+impl Slot {
+    pub const ALL: [Slot; 9] = [
+        Slot::Meta,
+        Slot::Frag,
+        Slot::Old,
+        Slot::New,
+        Slot::Whitespace,
+        Slot::Commit,
+        Slot::StatusAdded,
+        Slot::StatusChanged,
+        Slot::StatusUntracked,
+    ];
+
+    // The config key and the default colour
+    pub fn setting(self) -> (&'static str, &'static str) {
+        match self {
+            Slot::Meta => ("color.diff.meta", "bold"),
+            Slot::Frag => ("color.diff.frag", "cyan"),
+            Slot::Old => ("color.diff.old", "red"),
+            Slot::New => ("color.diff.new", "green"),
+            Slot::Whitespace => ("color.diff.whitespace", "normal red"),
+            Slot::Commit => ("color.diff.commit", "yellow"),
+            Slot::StatusAdded => ("color.status.added", "green"),
+            Slot::StatusChanged => ("color.status.changed", "red"),
+            Slot::StatusUntracked => ("color.status.untracked", "red"),
+        }
+    }
+}
+
+// The escape codes for each slot, or None when output isn't coloured
+static PALETTE: std::sync::OnceLock<Option<Vec<String>>> = std::sync::OnceLock::new();
+
+/// `text` in the colour of `slot`, or unchanged when output isn't coloured.
+pub fn paint(slot: Slot, text: &str) -> String {
+    match PALETTE.get() {
+        Some(Some(codes)) if !text.is_empty() && !codes[slot as usize].is_empty() => {
+            format!("\x1b[{}m{}\x1b[0m", codes[slot as usize], text)
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// "red", "bold blue", "brightwhite red", "208" or "#ff8700" as SGR codes. The first
+/// colour is the foreground and the second the background; "normal" skips one.
+pub fn parse_color(value: &str) -> Option<String> {
+    const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    const ATTRIBUTES: [(&str, u8); 7] = [("bold", 1), ("dim", 2), ("italic", 3), ("ul", 4), ("blink", 5), ("reverse", 7), ("strike", 9)];
+    let mut codes = Vec::new();
+    let mut colors = 0;
+    for word in value.split_whitespace() {
+        let word = word.to_lowercase();
+        if let Some((_, code)) = ATTRIBUTES.iter().find(|(name, _)| *name == word) {
+            codes.push(code.to_string());
+            continue;
+        }
+        let base = if colors == 0 { 30 } else { 40 };
+        colors += 1;
+        let hex = |digits: &str| u8::from_str_radix(digits, 16).ok();
+        let code = if word == "normal" {
+            continue;
+        } else if word == "default" {
+            (base + 9).to_string()
+        } else if let Some(i) = NAMES.iter().position(|name| *name == word) {
+            (base + i).to_string()
+        } else if let Some(i) = word.strip_prefix("bright").and_then(|name| NAMES.iter().position(|n| *n == name)) {
+            (base + 60 + i).to_string()
+        } else if let Ok(n) = word.parse::<u8>() {
+            format!("{};5;{}", base + 8, n)
+        } else if let Some(rgb) = word.strip_prefix('#').filter(|rgb| rgb.len() == 6 && rgb.is_ascii()) {
+            format!("{};2;{};{};{}", base + 8, hex(&rgb[..2])?, hex(&rgb[2..4])?, hex(&rgb[4..])?)
+        } else {
+            return None;
+        };
+        codes.push(code);
+    }
+    if colors > 2 {
+        return None;
+    }
+    Some(codes.join(";"))
+}
+
+/// When to colour output, from `--color` or color.ui.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+pub fn parse_color_when(value: &str) -> Result<ColorWhen> {
+    match value.to_lowercase().as_str() {
+        "auto" | "true" | "yes" | "on" => Ok(ColorWhen::Auto),
+        "always" => Ok(ColorWhen::Always),
+        "never" | "false" | "no" | "off" => Ok(ColorWhen::Never),
+        _ => Err(SnapError::InvalidArgument(format!("invalid color setting '{}' (expected auto, always or never)", value))),
+    }
+}
+
+/// Chooses the colours [`paint`] uses. `--color` wins over color.ui; left at auto,
+/// output is coloured when it goes to a terminal, unless NO_COLOR is set or TERM is dumb
+pub fn setup_colors(config: &Config, flag: Option<ColorWhen>) -> Result<()> {
+    let when = match (flag, config.get("color.ui")) {
+        (Some(when), _) => when,
+        (None, Some(value)) => parse_color_when(&value)?,
+        (None, None) => ColorWhen::Auto,
+    };
+    let env_set = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    let enabled = match when {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => {
+            std::io::stdout().is_terminal() && !env_set("NO_COLOR") && std::env::var("TERM").ok().as_deref() != Some("dumb")
+        }
+    };
+
+    let mut codes = Vec::new();
+    for slot in Slot::ALL {
+        let (key, default) = slot.setting();
+        // A bad value mustn't stop `config` from being used to fix it
+        let value = config.get(key).unwrap_or_else(|| default.to_string());
+        codes.push(parse_color(&value).unwrap_or_else(|| {
+            eprintln!("warning: invalid color '{}' for {}, using {}", value, key, default);
+            parse_color(default).unwrap_or_default()
+        }));
+    }
+    let _ = PALETTE.set(enabled.then_some(codes));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_become_sgr_codes() {
+        assert_eq!(parse_color("red").as_deref(), Some("31"));
+        assert_eq!(parse_color("bold blue white").as_deref(), Some("1;34;47"));
+        assert_eq!(parse_color("normal red").as_deref(), Some("41"));
+        assert_eq!(parse_color("brightwhite 208").as_deref(), Some("97;48;5;208"));
+        assert_eq!(parse_color("#ff8700").as_deref(), Some("38;2;255;135;0"));
+        assert_eq!(parse_color("red green blue"), None);
+        assert_eq!(parse_color("mauve"), None);
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{IoContext, Result};
+
+// A single INI-style config file. Lines are kept verbatim so `config set` and
+// `config unset` leave comments and formatting of untouched entries alone.
+pub struct ConfigFile {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+// "[core]" -> "core", "[branch \"main\"]" -> "branch.main"
+fn parse_section_header(line: &str) -> Option<String> {
+    let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?.trim();
+    match inner.split_once(char::is_whitespace) {
+        Some((section, sub)) => {
+            let sub = sub.trim().trim_matches('"');
+            Some(format!("{}.{}", section.to_lowercase(), sub))
+        }
+        None => Some(inner.to_lowercase()),
+    }
+}
+
+fn parse_config_entry(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') || line.starts_with('[') {
+        return None;
+    }
+    let (key, value) = match line.split_once('=') {
+        Some((key, value)) => (key, parse_config_value(value)),
+        // A bare key is true, and may have a comment too
+        None => (line.split(['#', ';']).next().unwrap_or(line), "true".to_string()),
+    };
+    Some((key.trim().to_lowercase(), value))
+}
+
+// Quotes are dropped, and `\"` and `\\` stand for `"` and `\`; other backslashes are
+// kept. Outside quotes `#` or `;` starts a comment and trailing whitespace goes.
+fn parse_config_value(raw: &str) -> String {
+    let mut value = String::with_capacity(raw.len());
+    let mut quoted = false;
+    // How much of `value` to keep: up to the last character that isn't unquoted whitespace
+    let mut kept = 0;
+    let mut chars = raw.trim_start().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if let Some(&next @ ('"' | '\\')) = chars.peek() => {
+                value.push(next);
+                chars.next();
+            }
+            '#' | ';' if !quoted => break,
+            c => value.push(c),
+        }
+        if quoted || !c.is_whitespace() {
+            kept = value.len();
+        }
+    }
+    value.truncate(kept);
+    value
+}
+
+// "init.defaultBranch" -> ("init", "defaultbranch"); subsections keep their case
+fn split_config_key(key: &str) -> Option<(String, String)> {
+    let (section, name) = key.rsplit_once('.')?;
+    if section.is_empty() || name.is_empty() {
+        return None;
+    }
+    let section = match section.split_once('.') {
+        Some((section, sub)) => format!("{}.{}", section.to_lowercase(), sub),
+        None => section.to_lowercase(),
+    };
+    Some((section, name.to_lowercase()))
+}
+
+impl ConfigFile {
+    pub fn load(path: PathBuf) -> ConfigFile {
+        let lines = fs::read_to_string(&path)
+            .map(|data| data.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        ConfigFile { path, lines }
+    }
+
+    // All entries as ("section.name", value), in file order
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut section = String::new();
+        let mut entries = Vec::new();
+        for line in &self.lines {
+            if let Some(name) = parse_section_header(line) {
+                section = name;
+            } else if let Some((name, value)) = parse_config_entry(line)
+                && !section.is_empty()
+            {
+                entries.push((format!("{}.{}", section, name), value));
+            }
+        }
+        entries
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let (section, name) = split_config_key(key)?;
+        let full_key = format!("{}.{}", section, name);
+        self.entries().into_iter().rev().find(|(k, _)| *k == full_key).map(|(_, v)| v)
+    }
+
+    // Returns false if the key isn't of the form "section.name"
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        let Some((section, name)) = split_config_key(key) else {
+            return false;
+        };
+        let value = if value.contains(['#', ';', '"', '\\']) || value.trim() != value {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            value.to_string()
+        };
+        let new_line = format!("\t{} = {}", name, value);
+
+        let mut current = String::new();
+        let mut existing = None;
+        let mut section_end = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(header) = parse_section_header(line) {
+                current = header;
+                if current == section {
+                    section_end = Some(i);
+                }
+            } else if current == section {
+                section_end = Some(i);
+                if parse_config_entry(line).is_some_and(|(k, _)| k == name) {
+                    existing = Some(i);
+                }
+            }
+        }
+
+        match (existing, section_end) {
+            (Some(i), _) => self.lines[i] = new_line,
+            (None, Some(i)) => self.lines.insert(i + 1, new_line),
+            (None, None) => {
+                let header = match section.split_once('.') {
+                    Some((section, sub)) => format!("[{} \"{}\"]", section, sub),
+                    None => format!("[{}]", section),
+                };
+                self.lines.push(header);
+                self.lines.push(new_line);
+            }
+        }
+        true
+    }
+
+    // Returns false if the key wasn't set
+    pub fn unset(&mut self, key: &str) -> bool {
+        let Some((section, name)) = split_config_key(key) else {
+            return false;
+        };
+        let mut current = String::new();
+        let before = self.lines.len();
+        self.lines.retain(|line| {
+            if let Some(header) = parse_section_header(line) {
+                current = header;
+                return true;
+            }
+            !(current == section && parse_config_entry(line).is_some_and(|(k, _)| k == name))
+        });
+        self.lines.len() != before
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut data = self.lines.join("\n");
+        data.push('\n');
+        fs::write(&self.path, data).at(&self.path)
+    }
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".snapconfig"))
+}
+
+/// The layered configuration: the repository's `.snap/config` wins over `~/.snapconfig`.
+pub struct Config {
+    repo: Option<ConfigFile>,
+    user: Option<ConfigFile>,
+}
+
+impl Config {
+    // Outside a repository only the user config is consulted
+    pub fn load(snap_dir: Option<&Path>) -> Config {
+        Config {
+            repo: snap_dir.map(|dir| ConfigFile::load(dir.join("config"))),
+            user: user_config_path().map(ConfigFile::load),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.repo.as_ref().and_then(|file| file.get(key)).or_else(|| self.user.as_ref().and_then(|file| file.get(key)))
+    }
+
+    pub fn list(&self) -> Vec<(String, String)> {
+        let mut entries = self.user.as_ref().map(ConfigFile::entries).unwrap_or_default();
+        entries.extend(self.repo.as_ref().map(ConfigFile::entries).unwrap_or_default());
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: &str) -> Option<String> {
+        let mut file = ConfigFile { path: PathBuf::new(), lines: Vec::new() };
+        assert!(file.set("user.name", value));
+        // Read back from the text as it would be saved
+        let reloaded = ConfigFile { path: PathBuf::new(), lines: file.lines.clone() };
+        reloaded.get("user.name")
+    }
+
+    #[test]
+    fn set_values_read_back_unchanged() {
+        for value in ["Ann Lee", " Ann \"Q\" Lee", "say \"hi\"", "C:\\Users\\ann", "a\\\"b", "# not a comment", "trailing ", "\"quoted\"", ""] {
+            assert_eq!(round_trip(value).as_deref(), Some(value), "value {:?}", value);
+        }
+    }
+
+    #[test]
+    fn inline_comments_are_stripped_outside_quotes() {
+        let lines = ["[user]", "\tname = Jane # me", "\temail = jane@example.com;work", "[core]", "\tpager = \"less # not a comment\" ; but this is", "\teditor = vi   ", "\tbare ; flag"];
+        let file = ConfigFile { path: PathBuf::new(), lines: lines.iter().map(|line| line.to_string()).collect() };
+        assert_eq!(file.get("user.name").as_deref(), Some("Jane"));
+        assert_eq!(file.get("user.email").as_deref(), Some("jane@example.com"));
+        assert_eq!(file.get("core.pager").as_deref(), Some("less # not a comment"));
+        assert_eq!(file.get("core.editor").as_deref(), Some("vi"));
+        assert_eq!(file.get("core.bare").as_deref(), Some("true"));
+    }
+
+    #[test]
+    fn quoted_values_are_unescaped() {
+        let file = ConfigFile { path: PathBuf::new(), lines: vec!["[user]".to_string(), "\tname = \"Ann \\\"Q\\\" \\\\ Lee\"".to_string()] };
+        assert_eq!(file.get("user.name").as_deref(), Some("Ann \"Q\" \\ Lee"));
+    }
+}
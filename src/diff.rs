@@ -0,0 +1,710 @@
+pub mod algorithm;
+pub mod word;
+
+use std::{borrow::Cow, collections::HashMap, ops::Range};
+
+use crate::error::Result;
+use crate::object::{hash_object, is_binary, ObjectStore, Tree, TreeEntry};
+
+pub use algorithm::{diff_sequences, Algorithm, Edit};
+
+/// Settings that change how file contents are compared.
+#[derive(Clone, Debug)]
+pub struct DiffOptions {
+    pub algorithm: Algorithm,
+    // Unchanged lines shown around each change
+    pub context: usize,
+    // Minimum similarity (percent) for a removed and an added file to count
+    // as a rename, or None to leave them apart
+    pub find_renames: Option<u8>,
+    // Same for an added file copied from one that was removed or modified
+    pub find_copies: Option<u8>,
+    // -w: lines that differ only in whitespace match
+    pub ignore_all_space: bool,
+    // -b: only changes in the amount of whitespace are ignored, and whitespace at line ends
+    pub ignore_space_change: bool,
+    // Changes that only add or remove blank lines don't start a hunk
+    pub ignore_blank_lines: bool,
+    // "\r\n" and "\n" line endings match
+    pub ignore_cr_at_eol: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions {
+            algorithm: Algorithm::default(),
+            context: 3,
+            find_renames: Some(50),
+            find_copies: None,
+            ignore_all_space: false,
+            ignore_space_change: false,
+            ignore_blank_lines: false,
+            ignore_cr_at_eol: false,
+        }
+    }
+}
+
+impl DiffOptions {
+    // Whether files with different contents can still have nothing to show
+    pub fn ignores_whitespace(&self) -> bool {
+        self.ignore_all_space || self.ignore_space_change || self.ignore_blank_lines || self.ignore_cr_at_eol
+    }
+
+    // The form of a line that is compared, after dropping whatever whitespace
+    // the options ignore; the line ending itself always counts
+    fn comparable<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if !self.ignore_all_space && !self.ignore_space_change && !self.ignore_cr_at_eol {
+            return Cow::Borrowed(line);
+        }
+        let (body, ending) = match line.strip_suffix('\n') {
+            Some(body) => (body, "\n"),
+            None => (line, ""),
+        };
+        let body = if self.ignore_all_space {
+            body.chars().filter(|c| !c.is_whitespace()).collect()
+        } else if self.ignore_space_change {
+            let indent = if body.starts_with(char::is_whitespace) && !body.trim().is_empty() { " " } else { "" };
+            format!("{}{}", indent, body.split_whitespace().collect::<Vec<_>>().join(" "))
+        } else {
+            body.strip_suffix('\r').unwrap_or(body).to_string()
+        };
+        Cow::Owned(body + ending)
+    }
+}
+
+// Past this many added or removed files only exact renames are looked for
+const RENAME_LIMIT: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChangeKind {
+    Removed,
+    Added,
+    Modified,
+    // A file became a directory or the other way round
+    TypeChanged,
+    // Moved or copied from `old_path`, with the similarity in percent
+    Renamed(u8),
+    Copied(u8),
+}
+
+impl ChangeKind {
+    // What `--name-status` shows, e.g. "M" or "R087"
+    pub fn status_code(self) -> String {
+        match self {
+            ChangeKind::Added => "A".to_string(),
+            ChangeKind::Removed => "D".to_string(),
+            ChangeKind::Modified => "M".to_string(),
+            ChangeKind::TypeChanged => "T".to_string(),
+            ChangeKind::Renamed(score) => format!("R{:03}", score),
+            ChangeKind::Copied(score) => format!("C{:03}", score),
+        }
+    }
+}
+
+/// One side of a comparison.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Snapshot {
+    Tree(String),
+    // Nothing at all, e.g. HEAD before the first commit
+    Empty,
+    Index,
+    // The tracked files as they are on disk
+    WorkTree,
+}
+
+/// One difference between two trees. Paths are relative to the worktree root.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TreeChange {
+    pub kind: ChangeKind,
+    pub path: String,
+    // Where a renamed or copied file came from
+    pub old_path: Option<String>,
+    pub old: Option<TreeEntry>,
+    pub new: Option<TreeEntry>,
+}
+
+impl TreeChange {
+    // The blob on each side, if that side is a file
+    pub fn old_blob(&self) -> Option<&str> {
+        match &self.old {
+            Some(TreeEntry::File { blob_hash, .. }) => Some(blob_hash),
+            _ => None,
+        }
+    }
+
+    pub fn new_blob(&self) -> Option<&str> {
+        match &self.new {
+            Some(TreeEntry::File { blob_hash, .. }) => Some(blob_hash),
+            _ => None,
+        }
+    }
+
+    // The file's path on each side, None where it isn't a file
+    pub fn old_file_path(&self) -> Option<&str> {
+        self.old_blob().map(|_| self.old_path.as_deref().unwrap_or(&self.path))
+    }
+
+    pub fn new_file_path(&self) -> Option<&str> {
+        self.new_blob().map(|_| self.path.as_str())
+    }
+
+    // "old -> new" for renames and copies, otherwise just the path
+    pub fn display_path(&self) -> String {
+        match &self.old_path {
+            Some(old_path) => format!("{} -> {}", old_path, self.path),
+            None => self.path.clone(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DiffLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+    // Follows the last line of a file that has no trailing newline
+    NoNewline,
+}
+
+impl DiffLine {
+    // The line as it appears in a unified diff
+    pub fn to_line(&self) -> String {
+        match self {
+            DiffLine::Context(line) => format!(" {}", line),
+            DiffLine::Removed(line) => format!("-{}", line),
+            DiffLine::Added(line) => format!("+{}", line),
+            DiffLine::NoNewline => "\\ No newline at end of file".to_string(),
+        }
+    }
+}
+
+/// A run of changes with its surrounding context. Starts are 1-based line numbers.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+impl Hunk {
+    // "@@ -a,b +c,d @@", leaving out counts of 1 like other diff tools
+    pub fn header(&self) -> String {
+        let range = |start: usize, len: usize| if len == 1 { start.to_string() } else { format!("{},{}", start, len) };
+        format!("@@ -{} +{} @@", range(self.old_start, self.old_lines), range(self.new_start, self.new_lines))
+    }
+}
+
+impl FileDiff {
+    // Lines added and removed, or None for binary files
+    pub fn line_counts(&self) -> Option<(usize, usize)> {
+        let FileDiff::Text(hunks) = self else {
+            return None;
+        };
+        let lines = hunks.iter().flat_map(|hunk| &hunk.lines);
+        let (mut added, mut removed) = (0, 0);
+        for line in lines {
+            match line {
+                DiffLine::Added(_) => added += 1,
+                DiffLine::Removed(_) => removed += 1,
+                _ => {}
+            }
+        }
+        Some((added, removed))
+    }
+}
+
+// "diff --git", "---" and "+++" lines; a missing side is /dev/null
+pub fn file_header(old_path: Option<&str>, new_path: Option<&str>) -> Vec<String> {
+    let name = |side: &str, path: Option<&str>| path.map_or("/dev/null".to_string(), |path| format!("{}/{}", side, path));
+    let (a, b) = (old_path.or(new_path).unwrap_or_default(), new_path.or(old_path).unwrap_or_default());
+    vec![format!("diff --git a/{} b/{}", a, b), format!("--- {}", name("a", old_path)), format!("+++ {}", name("b", new_path))]
+}
+
+// The lines between "diff --git" and "---": whether the file is new or deleted,
+// or where it was renamed or copied from
+pub fn extended_header(change: &TreeChange) -> Vec<String> {
+    let (verb, score) = match change.kind {
+        ChangeKind::Renamed(score) => ("rename", score),
+        ChangeKind::Copied(score) => ("copy", score),
+        _ if change.old_file_path().is_none() => return vec!["new file mode 100644".to_string()],
+        _ if change.new_file_path().is_none() => return vec!["deleted file mode 100644".to_string()],
+        _ => return Vec::new(),
+    };
+    let old_path = change.old_path.as_deref().unwrap_or_default();
+    vec![
+        format!("similarity index {}%", score),
+        format!("{} from {}", verb, old_path),
+        format!("{} to {}", verb, change.path),
+    ]
+}
+
+/// Renders one file's diff in unified format, as read by `patch` and `apply`.
+pub fn format_unified(change: &TreeChange, diff: &FileDiff) -> String {
+    let header = file_header(change.old_file_path(), change.new_file_path());
+    let mut out = format!("{}\n", header[0]);
+    for line in extended_header(change) {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    match diff {
+        FileDiff::Binary { .. } => out.push_str(&format!("Binary files {} and {} differ\n", &header[1][4..], &header[2][4..])),
+        FileDiff::Text(hunks) if !hunks.is_empty() => {
+            out.push_str(&format!("{}\n{}\n", header[1], header[2]));
+            for hunk in hunks {
+                out.push_str(&hunk.header());
+                out.push('\n');
+                for line in &hunk.lines {
+                    out.push_str(&line.to_line());
+                    out.push('\n');
+                }
+            }
+        }
+        FileDiff::Text(_) => {}
+    }
+    out
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum FileDiff {
+    // Sizes in bytes
+    Binary { old_size: usize, new_size: usize },
+    // No hunks means the contents are identical
+    Text(Vec<Hunk>),
+}
+
+/// Anything trees can be read from while comparing them.
+pub trait TreeSource {
+    fn tree(&self, hash: &str) -> Result<Tree>;
+}
+
+impl TreeSource for ObjectStore {
+    fn tree(&self, hash: &str) -> Result<Tree> {
+        self.read_tree(hash)
+    }
+}
+
+/// Trees built just for one comparison (of the index or the working tree), kept
+/// in memory in front of the object store instead of being written to it.
+pub struct TreeOverlay<'a> {
+    objects: &'a ObjectStore,
+    trees: HashMap<String, Tree>,
+}
+
+impl<'a> TreeOverlay<'a> {
+    pub fn new(objects: &'a ObjectStore) -> TreeOverlay<'a> {
+        TreeOverlay { objects, trees: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, tree: Tree) -> String {
+        let json = serde_json::to_string(&tree).expect("trees always serialize");
+        let hash = hash_object(json.as_bytes());
+        self.trees.insert(hash.clone(), tree);
+        hash
+    }
+}
+
+impl TreeSource for TreeOverlay<'_> {
+    fn tree(&self, hash: &str) -> Result<Tree> {
+        match self.trees.get(hash) {
+            Some(tree) => Ok(tree.clone()),
+            None => self.objects.read_tree(hash),
+        }
+    }
+}
+
+// Every file under a tree, as (path, entry)
+fn tree_files(objects: &(impl TreeSource + ?Sized), tree_hash: &str, prefix: &str, files: &mut Vec<(String, TreeEntry)>) -> Result<()> {
+    for entry in objects.tree(tree_hash)?.entries {
+        let path = format!("{}/{}", prefix, entry.name());
+        match &entry {
+            TreeEntry::File { .. } => files.push((path, entry)),
+            TreeEntry::Directory { tree_hash, .. } => tree_files(objects, tree_hash, &path, files)?,
+        }
+    }
+    Ok(())
+}
+
+// A whole directory that appeared or disappeared is reported file by file
+fn push_entry(objects: &(impl TreeSource + ?Sized), kind: ChangeKind, path: String, entry: &TreeEntry, changes: &mut Vec<TreeChange>) -> Result<()> {
+    let files = match entry {
+        TreeEntry::File { .. } => vec![(path, entry.clone())],
+        TreeEntry::Directory { tree_hash, .. } => {
+            let mut files = Vec::new();
+            tree_files(objects, tree_hash, &path, &mut files)?;
+            files
+        }
+    };
+    for (path, entry) in files {
+        let (old, new) = if kind == ChangeKind::Removed { (Some(entry), None) } else { (None, Some(entry)) };
+        changes.push(TreeChange { kind, path, old_path: None, old, new });
+    }
+    Ok(())
+}
+
+// Reports removed entries, then added ones, then modified ones; unchanged
+// subdirectories (same tree hash) are skipped without being read.
+pub fn compare_trees_recursive(
+    objects: &(impl TreeSource + ?Sized),
+    tree_hash_1: &str,
+    tree_hash_2: &str,
+    path_prefix: &str,
+) -> Result<Vec<TreeChange>> {
+    let tree_1 = objects.tree(tree_hash_1)?;
+    let tree_2 = objects.tree(tree_hash_2)?;
+    let mut changes = Vec::new();
+
+    let full_path = |name: &str| -> String {
+        if path_prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", path_prefix, name)
+        }
+    };
+
+    // get removed entries only in tree_1
+    for entry1 in &tree_1.entries {
+        if !tree_2.entries.iter().any(|e| e.name() == entry1.name()) {
+            push_entry(objects, ChangeKind::Removed, full_path(entry1.name()), entry1, &mut changes)?;
+        }
+    }
+
+    // get added entries only in tree_2
+    for entry2 in &tree_2.entries {
+        if !tree_1.entries.iter().any(|e| e.name() == entry2.name()) {
+            push_entry(objects, ChangeKind::Added, full_path(entry2.name()), entry2, &mut changes)?;
+        }
+    }
+
+    // get modified entries
+    for entry2 in &tree_2.entries {
+        let Some(entry1) = tree_1.entries.iter().find(|e| e.name() == entry2.name()) else {
+            continue;
+        };
+        if entry1.hash() == entry2.hash() {
+            continue;
+        }
+        let path = full_path(entry2.name());
+        match (entry1, entry2) {
+            (TreeEntry::File { .. }, TreeEntry::File { .. }) => {
+                changes.push(TreeChange { kind: ChangeKind::Modified, path, old_path: None, old: Some(entry1.clone()), new: Some(entry2.clone()) });
+            }
+            // recursively comparing the subdirectories to look for change
+            (TreeEntry::Directory { .. }, TreeEntry::Directory { .. }) => {
+                changes.extend(compare_trees_recursive(objects, entry1.hash(), entry2.hash(), &path)?);
+            }
+            // The directory side's files are listed after the type change itself
+            (TreeEntry::File { .. }, TreeEntry::Directory { .. }) => {
+                changes.push(TreeChange { kind: ChangeKind::TypeChanged, path: path.clone(), old_path: None, old: Some(entry1.clone()), new: Some(entry2.clone()) });
+                push_entry(objects, ChangeKind::Added, path, entry2, &mut changes)?;
+            }
+            (TreeEntry::Directory { .. }, TreeEntry::File { .. }) => {
+                changes.push(TreeChange { kind: ChangeKind::TypeChanged, path: path.clone(), old_path: None, old: Some(entry1.clone()), new: Some(entry2.clone()) });
+                push_entry(objects, ChangeKind::Removed, path, entry1, &mut changes)?;
+            }
+        }
+    }
+    Ok(changes)
+}
+
+// Keeps the changes under any of `paths`; no paths keeps everything. A rename
+// is kept when either of its paths matches.
+pub fn filter_paths(changes: Vec<TreeChange>, paths: &[String]) -> Vec<TreeChange> {
+    if paths.is_empty() {
+        return changes;
+    }
+    let matches = |path: &str| paths.iter().any(|dir| crate::index::in_directory(path, dir));
+    changes.into_iter().filter(|change| matches(&change.path) || change.old_path.as_deref().is_some_and(matches)).collect()
+}
+
+/// How much of two files' content is the same, in percent: the bytes of the
+/// lines they have in common against the size of the larger one.
+pub fn similarity(old_content: &[u8], new_content: &[u8]) -> u8 {
+    let larger = old_content.len().max(new_content.len());
+    if larger == 0 {
+        return 100;
+    }
+    let mut old_lines: HashMap<&[u8], usize> = HashMap::new();
+    for line in old_content.split_inclusive(|&b| b == b'\n') {
+        *old_lines.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in new_content.split_inclusive(|&b| b == b'\n') {
+        if let Some(count) = old_lines.get_mut(line)
+            && *count > 0
+        {
+            *count -= 1;
+            common += line.len();
+        }
+    }
+    (common * 100 / larger) as u8
+}
+
+/// Pairs removed files with added ones that have the same or similar content
+/// and reports them as renames, then (with `find_copies`) reports added files
+/// that resemble a removed or modified file as copies of it. Exact matches by
+/// blob hash are tried before comparing contents. `load` reads a change's old
+/// (false) or new (true) file.
+pub fn detect_renames(
+    changes: Vec<TreeChange>,
+    options: &DiffOptions,
+    load: &mut dyn FnMut(&TreeChange, bool) -> Result<Vec<u8>>,
+) -> Result<Vec<TreeChange>> {
+    let Some(rename_threshold) = options.find_renames.or(options.find_copies) else {
+        return Ok(changes);
+    };
+    let empty_blob = hash_object(b"");
+    let is_file = |change: &TreeChange, new_side: bool| {
+        let blob = if new_side { change.new_blob() } else { change.old_blob() };
+        blob.is_some_and(|blob| blob != empty_blob)
+    };
+    let removed: Vec<usize> = (0..changes.len()).filter(|&i| changes[i].kind == ChangeKind::Removed && is_file(&changes[i], false)).collect();
+    let added: Vec<usize> = (0..changes.len()).filter(|&i| changes[i].kind == ChangeKind::Added && is_file(&changes[i], true)).collect();
+
+    // (added, source, score); a source is used up by a rename but not by a copy
+    let mut renames = find_matches(&changes, &added, &removed, rename_threshold, true, load)?;
+    let mut copies = Vec::new();
+    if let Some(copy_threshold) = options.find_copies {
+        let renamed: Vec<usize> = renames.iter().map(|&(dest, _, _)| dest).collect();
+        let unmatched: Vec<usize> = added.iter().copied().filter(|i| !renamed.contains(i)).collect();
+        let sources: Vec<usize> = (0..changes.len())
+            .filter(|&i| matches!(changes[i].kind, ChangeKind::Removed | ChangeKind::Modified) && is_file(&changes[i], false))
+            .collect();
+        copies = find_matches(&changes, &unmatched, &sources, copy_threshold, false, load)?;
+    }
+
+    let mut dropped = vec![false; changes.len()];
+    let mut paired = Vec::new();
+    for (matches, renaming) in [(&mut renames, true), (&mut copies, false)] {
+        for &(dest, source, score) in matches.iter() {
+            let kind = if renaming { ChangeKind::Renamed(score) } else { ChangeKind::Copied(score) };
+            paired.push(TreeChange {
+                kind,
+                path: changes[dest].path.clone(),
+                old_path: Some(changes[source].path.clone()),
+                old: changes[source].old.clone(),
+                new: changes[dest].new.clone(),
+            });
+            dropped[dest] = true;
+            if renaming {
+                dropped[source] = true;
+            }
+        }
+    }
+    let mut result: Vec<TreeChange> = changes.into_iter().zip(dropped).filter(|(_, dropped)| !dropped).map(|(change, _)| change).collect();
+    result.extend(paired);
+    Ok(result)
+}
+
+// Matches each destination with at most one source, exact blob matches first and
+// then the most similar pairs at or above `threshold`
+fn find_matches(
+    changes: &[TreeChange],
+    dests: &[usize],
+    sources: &[usize],
+    threshold: u8,
+    consume: bool,
+    load: &mut dyn FnMut(&TreeChange, bool) -> Result<Vec<u8>>,
+) -> Result<Vec<(usize, usize, u8)>> {
+    let mut matches = Vec::new();
+    let mut used_sources = vec![false; sources.len()];
+    let mut open_dests = Vec::new();
+
+    for &dest in dests {
+        let blob = changes[dest].new_blob();
+        // Prefer a source with the same file name when several are identical
+        let name = |i: usize| changes[i].path.rsplit('/').next().unwrap_or_default().to_string();
+        let exact = (0..sources.len())
+            .filter(|&s| !used_sources[s] && changes[sources[s]].old_blob() == blob)
+            .min_by_key(|&s| name(sources[s]) != name(dest));
+        match exact {
+            Some(s) => {
+                matches.push((dest, sources[s], 100));
+                if consume {
+                    used_sources[s] = true;
+                }
+            }
+            None => open_dests.push(dest),
+        }
+    }
+
+    let open_sources: Vec<usize> = (0..sources.len()).filter(|&s| !used_sources[s]).collect();
+    if open_dests.is_empty() || open_sources.is_empty() || open_dests.len() > RENAME_LIMIT || open_sources.len() > RENAME_LIMIT {
+        return Ok(matches);
+    }
+
+    let mut source_contents = Vec::new();
+    for &s in &open_sources {
+        source_contents.push(load(&changes[sources[s]], false)?);
+    }
+    let mut candidates = Vec::new();
+    for &dest in &open_dests {
+        let content = load(&changes[dest], true)?;
+        for (k, source_content) in source_contents.iter().enumerate() {
+            // Files of very different sizes can't reach the threshold
+            let (small, large) = (content.len().min(source_content.len()), content.len().max(source_content.len()));
+            if small * 100 < large * threshold as usize {
+                continue;
+            }
+            // Only identical files (same blob) are a 100% match
+            let score = similarity(source_content, &content).min(99);
+            if score >= threshold {
+                candidates.push((score, dest, open_sources[k]));
+            }
+        }
+    }
+
+    // Best scores first; ties go to the earlier paths
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    let mut used_dests = vec![false; changes.len()];
+    for (score, dest, s) in candidates {
+        if used_dests[dest] || (consume && used_sources[s]) {
+            continue;
+        }
+        used_dests[dest] = true;
+        if consume {
+            used_sources[s] = true;
+        }
+        matches.push((dest, sources[s], score));
+    }
+    Ok(matches)
+}
+
+// A missing side (the file was added or removed) diffs as empty
+pub fn diff_blobs(objects: &ObjectStore, old_hash: Option<&str>, new_hash: Option<&str>, options: &DiffOptions) -> Result<FileDiff> {
+    let old_content = match old_hash {
+        Some(hash) => objects.read_blob(hash)?,
+        None => Vec::new(),
+    };
+    let new_content = match new_hash {
+        Some(hash) => objects.read_blob(hash)?,
+        None => Vec::new(),
+    };
+    Ok(diff_contents(&old_content, &new_content, options))
+}
+
+pub fn diff_contents(old_content: &[u8], new_content: &[u8], options: &DiffOptions) -> FileDiff {
+    if is_binary(old_content) || is_binary(new_content) {
+        return FileDiff::Binary { old_size: old_content.len(), new_size: new_content.len() };
+    }
+
+    // Text that isn't valid UTF-8 is still diffed line by line, just lossily displayed
+    let old_content = String::from_utf8_lossy(old_content);
+    let new_content = String::from_utf8_lossy(new_content);
+    let old_lines: Vec<&str> = old_content.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new_content.split_inclusive('\n').collect();
+    FileDiff::Text(diff_lines(&old_lines, &new_lines, options))
+}
+
+// Lines keep their "\n", so a last line without one never matches a line with one.
+// Whitespace the options ignore is left out when lines are matched up.
+pub fn diff_lines(old_lines: &[&str], new_lines: &[&str], options: &DiffOptions) -> Vec<Hunk> {
+    let edits = if options.ignores_whitespace() {
+        let old_keys: Vec<Cow<str>> = old_lines.iter().map(|line| options.comparable(line)).collect();
+        let new_keys: Vec<Cow<str>> = new_lines.iter().map(|line| options.comparable(line)).collect();
+        diff_sequences(&old_keys, &new_keys, options.algorithm)
+    } else {
+        diff_sequences(old_lines, new_lines, options.algorithm)
+    };
+    build_hunks(&edits, old_lines, new_lines, options)
+}
+
+// Groups an edit script into hunks with `options.context` lines around each change;
+// changes separated by at most twice that many unchanged lines share a hunk. With
+// `ignore_blank_lines`, blank lines added or removed only show inside other hunks.
+pub fn build_hunks(edits: &[Edit], old_lines: &[&str], new_lines: &[&str], options: &DiffOptions) -> Vec<Hunk> {
+    let context = options.context;
+    let is_blank = |line: &str| line.trim().is_empty();
+    let significant = |edit: &Edit| match *edit {
+        Edit::Equal { .. } => false,
+        Edit::Delete { old } => !options.ignore_blank_lines || !is_blank(old_lines[old]),
+        Edit::Insert { new } => !options.ignore_blank_lines || !is_blank(new_lines[new]),
+    };
+    let changes: Vec<usize> = (0..edits.len()).filter(|&i| significant(&edits[i])).collect();
+
+    // Line numbers consumed before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for edit in edits {
+        positions.push((old_pos, new_pos));
+        match edit {
+            Edit::Equal { .. } => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            Edit::Delete { .. } => old_pos += 1,
+            Edit::Insert { .. } => new_pos += 1,
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut c = 0;
+    while c < changes.len() {
+        let first = changes[c];
+        let mut last = first;
+        while c + 1 < changes.len() && changes[c + 1] - last - 1 <= 2 * context {
+            c += 1;
+            last = changes[c];
+        }
+        c += 1;
+
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(edits.len());
+        let mut hunk = Hunk { old_start: positions[start].0, old_lines: 0, new_start: positions[start].1, new_lines: 0, lines: Vec::new() };
+        for edit in &edits[start..end] {
+            // Context comes from the new side, which may differ in ignored whitespace
+            let text = match *edit {
+                Edit::Equal { new, .. } => new_lines[new],
+                Edit::Delete { old } => old_lines[old],
+                Edit::Insert { new } => new_lines[new],
+            };
+            let line = text.strip_suffix('\n').unwrap_or(text).to_string();
+            hunk.lines.push(match edit {
+                Edit::Equal { .. } => {
+                    hunk.old_lines += 1;
+                    hunk.new_lines += 1;
+                    DiffLine::Context(line)
+                }
+                Edit::Delete { .. } => {
+                    hunk.old_lines += 1;
+                    DiffLine::Removed(line)
+                }
+                Edit::Insert { .. } => {
+                    hunk.new_lines += 1;
+                    DiffLine::Added(line)
+                }
+            });
+            if !text.ends_with('\n') {
+                hunk.lines.push(DiffLine::NoNewline);
+            }
+        }
+        // An empty side starts at the line before the hunk
+        if hunk.old_lines > 0 {
+            hunk.old_start += 1;
+        }
+        if hunk.new_lines > 0 {
+            hunk.new_start += 1;
+        }
+        hunks.push(hunk);
+    }
+    hunks
+}
+
+/// Whitespace problems in an added line, as byte ranges: spaces directly before
+/// a tab in the indentation, and whitespace at the end.
+pub fn whitespace_errors(line: &str) -> Vec<Range<usize>> {
+    let trailing = line.trim_end().len();
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let mut errors = Vec::new();
+    if let Some(tab) = line[..indent.min(trailing)].rfind('\t') {
+        let spaces = tab - line[..tab].trim_end_matches(' ').len();
+        if spaces > 0 {
+            errors.push(tab - spaces..tab);
+        }
+    }
+    if trailing < line.len() {
+        errors.push(trailing..line.len());
+    }
+    errors
+}
@@ -0,0 +1,452 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// Which algorithm `diff_sequences` uses. All of them produce a valid edit script;
+/// Myers' is minimal, patience and histogram favour matching distinctive lines
+/// (so repeated blank lines and braces don't get paired up across functions).
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Option<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "myers" | "default" | "minimal" => Some(Algorithm::Myers),
+            "patience" => Some(Algorithm::Patience),
+            "histogram" => Some(Algorithm::Histogram),
+            _ => None,
+        }
+    }
+}
+
+/// One step of an edit script, indexing into the old and new sequences.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edit {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+/// Computes an edit script turning `old` into `new`. Edits come out in order, so
+/// walking them visits every element of both sequences exactly once, and between
+/// two equal elements every `Delete` comes before any `Insert`.
+pub fn diff_sequences<T: Eq + Hash>(old: &[T], new: &[T], algorithm: Algorithm) -> Vec<Edit> {
+    let mut differ = Differ { old, new, edits: Vec::new() };
+    match algorithm {
+        Algorithm::Myers => differ.myers(0, old.len(), 0, new.len()),
+        Algorithm::Patience => differ.patience(0, old.len(), 0, new.len()),
+        Algorithm::Histogram => differ.histogram(0, old.len(), 0, new.len()),
+    }
+    // Within each run of changes the removals come first, as diff and patch expect
+    let mut edits = differ.edits;
+    for run in edits.split_mut(|edit| matches!(edit, Edit::Equal { .. })) {
+        run.sort_by_key(|edit| matches!(edit, Edit::Insert { .. }));
+    }
+    edits
+}
+
+// Histogram diff ignores elements that occur more often than this, like git
+const MAX_CHAIN_LENGTH: usize = 64;
+
+struct Differ<'a, T> {
+    old: &'a [T],
+    new: &'a [T],
+    edits: Vec<Edit>,
+}
+
+impl<T: Eq + Hash> Differ<'_, T> {
+    // Emits the shared prefix and returns the remaining ranges and the shared suffix length
+    fn trim(&mut self, mut old_lo: usize, mut old_hi: usize, mut new_lo: usize, mut new_hi: usize) -> (usize, usize, usize, usize, usize) {
+        while old_lo < old_hi && new_lo < new_hi && self.old[old_lo] == self.new[new_lo] {
+            self.edits.push(Edit::Equal { old: old_lo, new: new_lo });
+            old_lo += 1;
+            new_lo += 1;
+        }
+        let mut suffix = 0;
+        while old_lo < old_hi && new_lo < new_hi && self.old[old_hi - 1] == self.new[new_hi - 1] {
+            old_hi -= 1;
+            new_hi -= 1;
+            suffix += 1;
+        }
+        (old_lo, old_hi, new_lo, new_hi, suffix)
+    }
+
+    fn push_suffix(&mut self, old_hi: usize, new_hi: usize, suffix: usize) {
+        for i in 0..suffix {
+            self.edits.push(Edit::Equal { old: old_hi + i, new: new_hi + i });
+        }
+    }
+
+    // Handles ranges where one side is empty; returns false if both have elements left
+    fn push_trivial(&mut self, old_lo: usize, old_hi: usize, new_lo: usize, new_hi: usize) -> bool {
+        if old_lo < old_hi && new_lo < new_hi {
+            return false;
+        }
+        self.edits.extend((old_lo..old_hi).map(|old| Edit::Delete { old }));
+        self.edits.extend((new_lo..new_hi).map(|new| Edit::Insert { new }));
+        true
+    }
+
+    // Linear-space Myers: find the middle snake of the shortest edit script, then
+    // solve both halves the same way.
+    fn myers(&mut self, old_lo: usize, old_hi: usize, new_lo: usize, new_hi: usize) {
+        let (old_lo, old_hi, new_lo, new_hi, suffix) = self.trim(old_lo, old_hi, new_lo, new_hi);
+        if !self.push_trivial(old_lo, old_hi, new_lo, new_hi) {
+            match self.middle_snake(old_lo, old_hi, new_lo, new_hi) {
+                Some((x, y)) => {
+                    self.myers(old_lo, x, new_lo, y);
+                    self.myers(x, old_hi, y, new_hi);
+                }
+                None => {
+                    self.edits.extend((old_lo..old_hi).map(|old| Edit::Delete { old }));
+                    self.edits.extend((new_lo..new_hi).map(|new| Edit::Insert { new }));
+                }
+            }
+        }
+        self.push_suffix(old_hi, new_hi, suffix);
+    }
+
+    // Runs the forward and reverse searches until their furthest-reaching paths
+    // overlap, and returns the point where the forward path ends as the split.
+    fn middle_snake(&self, old_lo: usize, old_hi: usize, new_lo: usize, new_hi: usize) -> Option<(usize, usize)> {
+        let old = &self.old[old_lo..old_hi];
+        let new = &self.new[new_lo..new_hi];
+        let (n, m) = (old.len() as isize, new.len() as isize);
+        let max_d = (n + m + 1) / 2;
+        let offset = max_d;
+        let len = (2 * max_d + 2) as usize;
+        let mut forward = vec![-1isize; len];
+        let mut reverse = vec![-1isize; len];
+        forward[(offset + 1) as usize] = 0;
+        reverse[(offset + 1) as usize] = 0;
+
+        let delta = n - m;
+        // With an odd delta the paths meet during a forward step, otherwise a reverse one
+        let check_forward = delta % 2 != 0;
+        let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+        for d in 0..max_d {
+            let mut k1 = -d + k1_start;
+            while k1 <= d - k1_end {
+                let idx = (offset + k1) as usize;
+                let mut x1 = if k1 == -d || (k1 != d && forward[idx - 1] < forward[idx + 1]) {
+                    forward[idx + 1]
+                } else {
+                    forward[idx - 1] + 1
+                };
+                let mut y1 = x1 - k1;
+                while x1 < n && y1 < m && old[x1 as usize] == new[y1 as usize] {
+                    x1 += 1;
+                    y1 += 1;
+                }
+                forward[idx] = x1;
+                if x1 > n {
+                    k1_end += 2;
+                } else if y1 > m {
+                    k1_start += 2;
+                } else if check_forward {
+                    let k2 = offset + delta - k1;
+                    if k2 >= 0 && (k2 as usize) < len && reverse[k2 as usize] != -1 && x1 >= n - reverse[k2 as usize] {
+                        return Some((old_lo + x1 as usize, new_lo + y1 as usize));
+                    }
+                }
+                k1 += 2;
+            }
+
+            let mut k2 = -d + k2_start;
+            while k2 <= d - k2_end {
+                let idx = (offset + k2) as usize;
+                let mut x2 = if k2 == -d || (k2 != d && reverse[idx - 1] < reverse[idx + 1]) {
+                    reverse[idx + 1]
+                } else {
+                    reverse[idx - 1] + 1
+                };
+                let mut y2 = x2 - k2;
+                while x2 < n && y2 < m && old[(n - x2 - 1) as usize] == new[(m - y2 - 1) as usize] {
+                    x2 += 1;
+                    y2 += 1;
+                }
+                reverse[idx] = x2;
+                if x2 > n {
+                    k2_end += 2;
+                } else if y2 > m {
+                    k2_start += 2;
+                } else if !check_forward {
+                    let k1 = offset + delta - k2;
+                    if k1 >= 0 && (k1 as usize) < len && forward[k1 as usize] != -1 {
+                        let x1 = forward[k1 as usize];
+                        let y1 = offset + x1 - k1;
+                        if x1 >= n - x2 {
+                            return Some((old_lo + x1 as usize, new_lo + y1 as usize));
+                        }
+                    }
+                }
+                k2 += 2;
+            }
+        }
+        None
+    }
+
+    // Patience diff: anchor on elements that occur exactly once on each side, keep the
+    // longest run of anchors that appear in the same order, and recurse between them.
+    fn patience(&mut self, old_lo: usize, old_hi: usize, new_lo: usize, new_hi: usize) {
+        let (old_lo, old_hi, new_lo, new_hi, suffix) = self.trim(old_lo, old_hi, new_lo, new_hi);
+        if !self.push_trivial(old_lo, old_hi, new_lo, new_hi) {
+            let anchors = self.unique_anchors(old_lo, old_hi, new_lo, new_hi);
+            if anchors.is_empty() {
+                self.myers(old_lo, old_hi, new_lo, new_hi);
+            } else {
+                let (mut old_pos, mut new_pos) = (old_lo, new_lo);
+                for (old, new) in anchors {
+                    self.patience(old_pos, old, new_pos, new);
+                    self.edits.push(Edit::Equal { old, new });
+                    old_pos = old + 1;
+                    new_pos = new + 1;
+                }
+                self.patience(old_pos, old_hi, new_pos, new_hi);
+            }
+        }
+        self.push_suffix(old_hi, new_hi, suffix);
+    }
+
+    // Elements unique in both ranges, as (old, new) positions forming the longest
+    // increasing subsequence by new position
+    fn unique_anchors(&self, old_lo: usize, old_hi: usize, new_lo: usize, new_hi: usize) -> Vec<(usize, usize)> {
+        // element -> (occurrences in old, last position in old)
+        let mut counts: HashMap<&T, (usize, usize)> = HashMap::new();
+        for i in old_lo..old_hi {
+            let entry = counts.entry(&self.old[i]).or_insert((0, i));
+            entry.0 += 1;
+        }
+        let mut candidates = Vec::new();
+        let mut new_counts: HashMap<&T, usize> = HashMap::new();
+        for j in new_lo..new_hi {
+            *new_counts.entry(&self.new[j]).or_insert(0) += 1;
+        }
+        for j in new_lo..new_hi {
+            if let Some(&(1, i)) = counts.get(&self.new[j])
+                && new_counts[&self.new[j]] == 1
+            {
+                candidates.push((i, j));
+            }
+        }
+        candidates.sort();
+
+        // Patience sorting over the new positions, in old order
+        let mut piles: Vec<usize> = Vec::new();
+        let mut back: Vec<Option<usize>> = vec![None; candidates.len()];
+        for (c, &(_, new)) in candidates.iter().enumerate() {
+            let pile = piles.partition_point(|&top| candidates[top].1 < new);
+            if pile > 0 {
+                back[c] = Some(piles[pile - 1]);
+            }
+            if pile == piles.len() {
+                piles.push(c);
+            } else {
+                piles[pile] = c;
+            }
+        }
+
+        let mut anchors = Vec::new();
+        let mut current = piles.last().copied();
+        while let Some(c) = current {
+            anchors.push(candidates[c]);
+            current = back[c];
+        }
+        anchors.reverse();
+        anchors
+    }
+
+    // Histogram diff: split around the longest common region containing the
+    // element that occurs least often in the old range, then recurse on both sides.
+    fn histogram(&mut self, old_lo: usize, old_hi: usize, new_lo: usize, new_hi: usize) {
+        let (old_lo, old_hi, new_lo, new_hi, suffix) = self.trim(old_lo, old_hi, new_lo, new_hi);
+        if !self.push_trivial(old_lo, old_hi, new_lo, new_hi) {
+            match self.lowest_occurrence_region(old_lo, old_hi, new_lo, new_hi) {
+                Some((old_start, new_start, len)) => {
+                    self.histogram(old_lo, old_start, new_lo, new_start);
+                    for i in 0..len {
+                        self.edits.push(Edit::Equal { old: old_start + i, new: new_start + i });
+                    }
+                    self.histogram(old_start + len, old_hi, new_start + len, new_hi);
+                }
+                None => self.myers(old_lo, old_hi, new_lo, new_hi),
+            }
+        }
+        self.push_suffix(old_hi, new_hi, suffix);
+    }
+
+    fn lowest_occurrence_region(&self, old_lo: usize, old_hi: usize, new_lo: usize, new_hi: usize) -> Option<(usize, usize, usize)> {
+        let mut occurrences: HashMap<&T, Vec<usize>> = HashMap::new();
+        for i in old_lo..old_hi {
+            occurrences.entry(&self.old[i]).or_default().push(i);
+        }
+
+        // (count, old start, new start, length)
+        let mut best: Option<(usize, usize, usize, usize)> = None;
+        for j in new_lo..new_hi {
+            let Some(positions) = occurrences.get(&self.new[j]) else {
+                continue;
+            };
+            if positions.len() > MAX_CHAIN_LENGTH {
+                continue;
+            }
+            for &i in positions {
+                let (mut old_start, mut new_start) = (i, j);
+                while old_start > old_lo && new_start > new_lo && self.old[old_start - 1] == self.new[new_start - 1] {
+                    old_start -= 1;
+                    new_start -= 1;
+                }
+                let (mut old_end, mut new_end) = (i + 1, j + 1);
+                while old_end < old_hi && new_end < new_hi && self.old[old_end] == self.new[new_end] {
+                    old_end += 1;
+                    new_end += 1;
+                }
+                let count = (old_start..old_end).map(|k| occurrences[&self.old[k]].len()).min().unwrap_or(usize::MAX);
+                let len = old_end - old_start;
+                let better = match best {
+                    None => true,
+                    Some((best_count, _, _, best_len)) => count < best_count || (count == best_count && len > best_len),
+                };
+                if better {
+                    best = Some((count, old_start, new_start, len));
+                }
+            }
+        }
+        best.map(|(_, old_start, new_start, len)| (old_start, new_start, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 3] = [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram];
+
+    // Checks the script walks both sequences in order, and returns its number of changes
+    fn check_script<T: Eq + Hash + std::fmt::Debug>(old: &[T], new: &[T], edits: &[Edit]) -> usize {
+        let (mut old_pos, mut new_pos, mut changes) = (0, 0, 0);
+        let mut inserted = false;
+        for edit in edits {
+            match *edit {
+                Edit::Equal { old: o, new: n } => {
+                    assert_eq!((o, n), (old_pos, new_pos));
+                    assert_eq!(old[o], new[n]);
+                    old_pos += 1;
+                    new_pos += 1;
+                    inserted = false;
+                }
+                Edit::Delete { old: o } => {
+                    assert_eq!(o, old_pos);
+                    assert!(!inserted, "delete after insert in {:?}", edits);
+                    old_pos += 1;
+                    changes += 1;
+                }
+                Edit::Insert { new: n } => {
+                    assert_eq!(n, new_pos);
+                    new_pos += 1;
+                    changes += 1;
+                    inserted = true;
+                }
+            }
+        }
+        assert_eq!((old_pos, new_pos), (old.len(), new.len()));
+        changes
+    }
+
+    // The length of the longest common subsequence, by dynamic programming
+    fn lcs_len<T: Eq>(old: &[T], new: &[T]) -> usize {
+        let mut table = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                table[i][j] = if old[i] == new[j] { table[i + 1][j + 1] + 1 } else { table[i + 1][j].max(table[i][j + 1]) };
+            }
+        }
+        table[0][0]
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    // Small pseudo-random sequences over a few symbols, so they share a lot
+    fn sequences() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut state: u32 = 12345;
+        let mut next = move |bound: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) % bound
+        };
+        (0..500)
+            .map(|_| {
+                let (old_len, new_len) = (next(12) as usize, next(12) as usize);
+                let old = (0..old_len).map(|_| b'a' + next(4) as u8).collect();
+                let new = (0..new_len).map(|_| b'a' + next(4) as u8).collect();
+                (old, new)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_and_empty_sequences() {
+        for algorithm in ALGORITHMS {
+            let same = chars("abc");
+            let edits = diff_sequences(&same, &same, algorithm);
+            assert_eq!(edits, vec![Edit::Equal { old: 0, new: 0 }, Edit::Equal { old: 1, new: 1 }, Edit::Equal { old: 2, new: 2 }]);
+            assert_eq!(diff_sequences(&same, &[], algorithm), vec![Edit::Delete { old: 0 }, Edit::Delete { old: 1 }, Edit::Delete { old: 2 }]);
+            assert_eq!(diff_sequences(&[], &same, algorithm), vec![Edit::Insert { new: 0 }, Edit::Insert { new: 1 }, Edit::Insert { new: 2 }]);
+        }
+    }
+
+    #[test]
+    fn replaced_element_is_deleted_then_inserted() {
+        for algorithm in ALGORITHMS {
+            let edits = diff_sequences(&chars("xay"), &chars("xby"), algorithm);
+            assert_eq!(edits, vec![Edit::Equal { old: 0, new: 0 }, Edit::Delete { old: 1 }, Edit::Insert { new: 1 }, Edit::Equal { old: 2, new: 2 }]);
+        }
+    }
+
+    #[test]
+    fn myers_scripts_are_minimal() {
+        // The example from Myers' paper needs five changes
+        let (old, new) = (chars("abcabba"), chars("cbabac"));
+        assert_eq!(check_script(&old, &new, &diff_sequences(&old, &new, Algorithm::Myers)), 5);
+
+        for (old, new) in sequences() {
+            let edits = diff_sequences(&old, &new, Algorithm::Myers);
+            let changes = check_script(&old, &new, &edits);
+            assert_eq!(changes, old.len() + new.len() - 2 * lcs_len(&old, &new), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn every_algorithm_gives_an_ordered_script() {
+        for (old, new) in sequences() {
+            for algorithm in ALGORITHMS {
+                check_script(&old, &new, &diff_sequences(&old, &new, algorithm));
+            }
+        }
+    }
+
+    #[test]
+    fn patience_and_histogram_anchor_on_distinctive_lines() {
+        // A function inserted before another: Myers pairs up the braces, the
+        // others keep the unique lines of the existing function together
+        let old = ["fn a() {", "    one();", "}"];
+        let new = ["fn b() {", "    two();", "}", "", "fn a() {", "    one();", "}"];
+        for algorithm in [Algorithm::Patience, Algorithm::Histogram] {
+            let edits = diff_sequences(&old, &new, algorithm);
+            check_script(&old, &new, &edits);
+            let kept: Vec<(usize, usize)> = edits
+                .iter()
+                .filter_map(|edit| match *edit {
+                    Edit::Equal { old, new } => Some((old, new)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(kept, vec![(0, 4), (1, 5), (2, 6)], "{:?}", algorithm);
+        }
+    }
+}
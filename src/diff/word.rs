@@ -0,0 +1,184 @@
+use regex::Regex;
+
+use super::algorithm::{diff_sequences, Algorithm, Edit};
+use super::{DiffLine, Hunk};
+
+/// A piece of a word diff. The text never contains a newline; `Newline` ends a line.
+#[derive(Clone, PartialEq, Debug)]
+pub enum WordChunk {
+    Equal(String),
+    Removed(String),
+    Added(String),
+    Newline,
+}
+
+/// What counts as a word when none is configured: a run of non-whitespace.
+/// `.` instead compares character by character.
+pub fn default_word_regex() -> Regex {
+    Regex::new(r"\S+").expect("the default word regex is valid")
+}
+
+// A word and the text between it and the word before
+struct Word<'a> {
+    gap: &'a str,
+    text: &'a str,
+}
+
+// The matches of `regex` in `text`, and whatever follows the last one
+fn split_words<'a>(text: &'a str, regex: &Regex) -> (Vec<Word<'a>>, &'a str) {
+    let mut words = Vec::new();
+    let mut end = 0;
+    for found in regex.find_iter(text).filter(|found| !found.is_empty()) {
+        words.push(Word { gap: &text[end..found.start()], text: found.as_str() });
+        end = found.end();
+    }
+    (words, &text[end..])
+}
+
+// Appends text as chunks of one kind, turning newlines into `Newline`
+fn push(chunks: &mut Vec<WordChunk>, make: fn(String) -> WordChunk, text: &str) {
+    for (i, piece) in text.split('\n').enumerate() {
+        if i > 0 {
+            chunks.push(WordChunk::Newline);
+        }
+        if piece.is_empty() {
+            continue;
+        }
+        // Neighbouring pieces of the same kind read better as one
+        match (chunks.last_mut(), make(piece.to_string())) {
+            (Some(WordChunk::Equal(last)), WordChunk::Equal(text))
+            | (Some(WordChunk::Removed(last)), WordChunk::Removed(text))
+            | (Some(WordChunk::Added(last)), WordChunk::Added(text)) => last.push_str(&text),
+            (_, chunk) => chunks.push(chunk),
+        }
+    }
+}
+
+/// Compares two texts word by word. Whitespace between words is taken from the
+/// new text and never shows up as a change by itself. Each run of changed words
+/// comes out as its removals followed by its additions.
+pub fn diff_words(old: &str, new: &str, regex: &Regex, algorithm: Algorithm) -> Vec<WordChunk> {
+    let (old_words, _) = split_words(old, regex);
+    let (new_words, new_tail) = split_words(new, regex);
+    let old_texts: Vec<&str> = old_words.iter().map(|word| word.text).collect();
+    let new_texts: Vec<&str> = new_words.iter().map(|word| word.text).collect();
+
+    let mut chunks = Vec::new();
+    let (mut removed, mut added): (Vec<&Word>, Vec<&Word>) = (Vec::new(), Vec::new());
+    for edit in diff_sequences(&old_texts, &new_texts, algorithm) {
+        match edit {
+            Edit::Equal { new, .. } => {
+                push_change(&mut chunks, &removed, &added);
+                removed.clear();
+                added.clear();
+                push(&mut chunks, WordChunk::Equal, new_words[new].gap);
+                push(&mut chunks, WordChunk::Equal, new_words[new].text);
+            }
+            Edit::Delete { old } => removed.push(&old_words[old]),
+            Edit::Insert { new } => added.push(&new_words[new]),
+        }
+    }
+    push_change(&mut chunks, &removed, &added);
+    push(&mut chunks, WordChunk::Equal, new_tail);
+    chunks
+}
+
+// One run of changed words. The blank gap in front of the run separates it from
+// what came before, once; gaps inside it belong to the removed or added text.
+fn push_change(chunks: &mut Vec<WordChunk>, removed: &[&Word], added: &[&Word]) {
+    let is_blank = |gap: &str| gap.chars().all(char::is_whitespace);
+    match (removed.first(), added.first()) {
+        (_, Some(first)) if is_blank(first.gap) => push(chunks, WordChunk::Equal, first.gap),
+        // Nothing takes the removed words' place, so a line break before them isn't kept
+        (Some(first), None) if is_blank(first.gap) => {
+            let line_start = matches!(chunks.last(), None | Some(WordChunk::Newline));
+            push(chunks, WordChunk::Equal, if first.gap.is_empty() || line_start { "" } else { " " });
+        }
+        _ => {}
+    }
+    if let Some(first) = removed.first()
+        && !is_blank(first.gap)
+    {
+        push(chunks, WordChunk::Removed, first.gap);
+    }
+    push_words(chunks, WordChunk::Removed, removed, true);
+    if let Some(first) = added.first()
+        && !is_blank(first.gap)
+    {
+        push(chunks, WordChunk::Added, first.gap);
+    }
+    push_words(chunks, WordChunk::Added, added, false);
+}
+
+// The words of one side, without the gap before the first. Line breaks between
+// removed words become spaces, since the new text has no lines for them.
+fn push_words(chunks: &mut Vec<WordChunk>, make: fn(String) -> WordChunk, words: &[&Word], removed: bool) {
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            let blank = word.gap.chars().all(char::is_whitespace);
+            push(chunks, make, &if removed && blank { word.gap.replace('\n', " ") } else { word.gap.to_string() });
+        }
+        push(chunks, make, word.text);
+    }
+}
+
+/// A hunk as a word diff: context lines as they are, and each run of removed and
+/// added lines compared word by word.
+pub fn word_diff_hunk(hunk: &Hunk, regex: &Regex, algorithm: Algorithm) -> Vec<WordChunk> {
+    let mut chunks = Vec::new();
+    let (mut removed, mut added): (Vec<&str>, Vec<&str>) = (Vec::new(), Vec::new());
+    let flush = |chunks: &mut Vec<WordChunk>, removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        if !removed.is_empty() || !added.is_empty() {
+            chunks.extend(diff_words(&removed.join("\n"), &added.join("\n"), regex, algorithm));
+            chunks.push(WordChunk::Newline);
+            removed.clear();
+            added.clear();
+        }
+    };
+
+    for line in &hunk.lines {
+        match line {
+            DiffLine::Removed(text) => removed.push(text),
+            DiffLine::Added(text) => added.push(text),
+            DiffLine::Context(text) => {
+                flush(&mut chunks, &mut removed, &mut added);
+                push(&mut chunks, WordChunk::Equal, text);
+                chunks.push(WordChunk::Newline);
+            }
+            DiffLine::NoNewline => {}
+        }
+    }
+    flush(&mut chunks, &mut removed, &mut added);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(old: &str, new: &str) -> String {
+        let mut out = String::new();
+        for chunk in diff_words(old, new, &default_word_regex(), Algorithm::Myers) {
+            match chunk {
+                WordChunk::Equal(text) => out.push_str(&text),
+                WordChunk::Removed(text) => out.push_str(&format!("[-{}-]", text)),
+                WordChunk::Added(text) => out.push_str(&format!("{{+{}+}}", text)),
+                WordChunk::Newline => out.push('\n'),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn replaced_word_shows_removal_first() {
+        assert_eq!(plain("the lazy dog.", "the lazy dog!"), "the lazy [-dog.-]{+dog!+}");
+        assert_eq!(plain("one two three", "one 2 3"), "one [-two three-]{+2 3+}");
+    }
+
+    #[test]
+    fn separating_whitespace_appears_once() {
+        assert_eq!(plain("a b c", "a c"), "a [-b-] c");
+        assert_eq!(plain("a c", "a b c"), "a {+b+} c");
+        assert_eq!(plain("a\nb c", "a\nc"), "a [-b-]\nc");
+    }
+}
@@ -0,0 +1,67 @@
+use std::{io, path::Path};
+
+#[derive(Debug)]
+pub enum SnapError {
+    NotARepository,
+    BadRevision(String),
+    Conflict(String),
+    CorruptObject { hash: String, reason: String },
+    CorruptIndex(String),
+    Io { path: String, source: io::Error },
+    Usage(String),
+    InvalidArgument(String),
+    ConfigKeyNotFound(String),
+}
+
+pub type Result<T> = std::result::Result<T, SnapError>;
+
+impl SnapError {
+    // Distinct exit codes so scripts can tell failures apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SnapError::ConfigKeyNotFound(_) => 1,
+            SnapError::Usage(_) | SnapError::InvalidArgument(_) => 2,
+            SnapError::NotARepository => 3,
+            SnapError::BadRevision(_) => 4,
+            SnapError::Conflict(_) => 5,
+            SnapError::Io { .. } => 6,
+            SnapError::CorruptObject { .. } | SnapError::CorruptIndex(_) => 7,
+        }
+    }
+}
+
+impl std::fmt::Display for SnapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapError::NotARepository => write!(f, "not a snap repository (or any of the parent directories): .snap"),
+            SnapError::BadRevision(rev) => write!(f, "unknown revision or branch '{}'", rev),
+            SnapError::Conflict(message) => write!(f, "{}", message),
+            SnapError::CorruptObject { hash, reason } => write!(f, "object {}: {}", hash, reason),
+            SnapError::CorruptIndex(reason) => write!(f, "index file is corrupt: {}", reason),
+            SnapError::Io { path, source } => write!(f, "{}: {}", path, source),
+            SnapError::Usage(message) => write!(f, "{}", message),
+            SnapError::InvalidArgument(message) => write!(f, "{}", message),
+            SnapError::ConfigKeyNotFound(key) => write!(f, "config key '{}' is not set", key),
+        }
+    }
+}
+
+impl std::error::Error for SnapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Attaches the path being accessed to an I/O error
+pub(crate) trait IoContext<T> {
+    fn at(self, path: impl AsRef<Path>) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn at(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|source| SnapError::Io { path: path.as_ref().display().to_string(), source })
+    }
+}
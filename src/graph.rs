@@ -0,0 +1,137 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::error::Result;
+use crate::repository::Repository;
+
+// Which of the two commits a merge-base search reached a commit from
+const FROM_A: u8 = 1;
+const FROM_B: u8 = 2;
+// Below a common ancestor already found, so not a candidate itself
+const STALE: u8 = 4;
+
+// Queries over the commit graph, following every parent
+impl Repository {
+    /// The best common ancestors of `a` and `b`, newest first: the commits both
+    /// can reach that no other such commit descends from. Usually there is one;
+    /// criss-cross merges can leave several, and unrelated histories none.
+    pub fn merge_bases(&self, a: &str, b: &str) -> Result<Vec<String>> {
+        if a == b {
+            return Ok(vec![a.to_string()]);
+        }
+
+        // Walk down from both commits at once, newest first, marking where each commit was reached from
+        let mut flags: HashMap<String, u8> = HashMap::from([(a.to_string(), FROM_A), (b.to_string(), FROM_B)]);
+        let mut queue = BinaryHeap::from([(self.read_commit(a)?.timestamp, a.to_string()), (self.read_commit(b)?.timestamp, b.to_string())]);
+        let mut candidates = Vec::new();
+        while queue.iter().any(|(_, hash)| flags[hash] & STALE == 0) {
+            let Some((_, hash)) = queue.pop() else {
+                break;
+            };
+            let mut flag = flags[&hash];
+            if flag & (FROM_A | FROM_B) == FROM_A | FROM_B {
+                if flag & STALE == 0 {
+                    candidates.push(hash.clone());
+                }
+                flag |= STALE;
+                // Recorded so the commit isn't taken again if it was queued from both sides
+                flags.insert(hash.clone(), flag);
+            }
+            for parent in self.read_commit(&hash)?.parents {
+                let parent_flags = flags.entry(parent.clone()).or_default();
+                if *parent_flags & flag == flag {
+                    continue;
+                }
+                *parent_flags |= flag;
+                queue.push((self.read_commit(&parent)?.timestamp, parent));
+            }
+        }
+
+        // Timestamps can be skewed, so a candidate may still be an ancestor of another
+        if candidates.len() > 1 {
+            let mut parents = Vec::new();
+            for hash in &candidates {
+                parents.extend(self.read_commit(hash)?.parents);
+            }
+            let below = self.reachable(parents)?;
+            candidates.retain(|hash| !below.contains(hash));
+        }
+        Ok(candidates)
+    }
+
+    /// The best common ancestor of `a` and `b` (the newest, if there are several),
+    /// or None if their histories are unrelated.
+    pub fn merge_base(&self, a: &str, b: &str) -> Result<Option<String>> {
+        Ok(self.merge_bases(a, b)?.into_iter().next())
+    }
+
+    /// Whether `ancestor` is `descendant` itself or reachable from it, i.e. whether
+    /// a branch at `descendant` contains that commit.
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        Ok(self.merge_bases(ancestor, descendant)?.iter().any(|hash| hash == ancestor))
+    }
+
+    // The commits in `starts` and every commit reachable from them
+    pub(crate) fn reachable(&self, starts: Vec<String>) -> Result<HashSet<String>> {
+        let mut seen = HashSet::new();
+        let mut pending = starts;
+        while let Some(hash) = pending.pop() {
+            if hash.is_empty() || !seen.insert(hash.clone()) {
+                continue;
+            }
+            pending.extend(self.read_commit(&hash)?.parents);
+        }
+        Ok(seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::object::Commit;
+    use crate::repository::Repository;
+    use crate::testutil::TestRepo;
+
+    fn commit(repo: &Repository, message: &str, parents: &[&str], timestamp: i64) -> String {
+        let commit = Commit {
+            tree_hash: String::new(),
+            parents: parents.iter().map(|parent| parent.to_string()).collect(),
+            timestamp,
+            message: message.to_string(),
+            author: None,
+            committer: None,
+        };
+        repo.objects().save_commit(&commit).unwrap()
+    }
+
+    #[test]
+    fn fork_has_one_merge_base() {
+        let repo = TestRepo::new("graph-fork");
+        // The base is reached from both sides before it is taken off the queue
+        let root = commit(&repo, "root", &[], 1);
+        let base = commit(&repo, "base", &[&root], 2);
+        let main = commit(&repo, "main", &[&base], 3);
+        let feat = commit(&repo, "feat", &[&base], 4);
+        let feat2 = commit(&repo, "feat 2", &[&feat], 5);
+
+        assert_eq!(repo.merge_bases(&main, &feat2).unwrap(), vec![base.clone()]);
+        assert_eq!(repo.merge_bases(&feat2, &main).unwrap(), vec![base.clone()]);
+        assert!(repo.is_ancestor(&base, &main).unwrap());
+        assert!(!repo.is_ancestor(&main, &feat2).unwrap());
+    }
+
+    #[test]
+    fn criss_cross_has_two_merge_bases() {
+        let repo = TestRepo::new("graph-criss-cross");
+        // Commits made within the same second
+        let root = commit(&repo, "root", &[], 1);
+        let a = commit(&repo, "a", &[&root], 1);
+        let b = commit(&repo, "b", &[&root], 1);
+        let left = commit(&repo, "left", &[&a, &b], 1);
+        let right = commit(&repo, "right", &[&b, &a], 1);
+
+        let mut bases = repo.merge_bases(&left, &right).unwrap();
+        bases.sort();
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(bases, expected);
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{IoContext, Result, SnapError};
+
+// The index holds the full tracked tree: path (relative to the worktree root) -> blob hash
+pub type Index = HashMap<String, String>;
+
+/// One version of a conflicted path: the merge base's, ours or theirs.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stage {
+    Base,
+    Ours,
+    Theirs,
+}
+
+/// The versions of a path a merge couldn't reconcile, as blob hashes. A version
+/// is missing where that side doesn't have the file.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct ConflictEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ours: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theirs: Option<String>,
+}
+
+impl ConflictEntry {
+    pub fn version(&self, stage: Stage) -> Option<&str> {
+        match stage {
+            Stage::Base => self.base.as_deref(),
+            Stage::Ours => self.ours.as_deref(),
+            Stage::Theirs => self.theirs.as_deref(),
+        }
+    }
+
+    /// How `status` describes the conflict, e.g. "both modified".
+    pub fn label(&self) -> &'static str {
+        match (self.base.is_some(), self.ours.is_some(), self.theirs.is_some()) {
+            (true, true, true) => "both modified",
+            (false, true, true) => "both added",
+            (true, false, true) => "deleted by us",
+            (true, true, false) => "deleted by them",
+            (false, true, false) => "added by us",
+            (false, false, true) => "added by them",
+            (_, false, false) => "both deleted",
+        }
+    }
+}
+
+// Unresolved paths, sorted
+pub type Conflicts = BTreeMap<String, ConflictEntry>;
+
+// The INDEX format written now; an index without a version predates it
+const INDEX_VERSION: u32 = 2;
+
+// On disk a path maps to its blob hash, or to its versions while it's conflicted
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum IndexEntry {
+    Staged(String),
+    Conflicted(ConflictEntry),
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile {
+    version: u32,
+    entries: HashMap<String, IndexEntry>,
+}
+
+// Older versions wrote the entries as a bare map and emptied it after every commit
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredIndex {
+    Versioned(IndexFile),
+    Legacy(HashMap<String, IndexEntry>),
+}
+
+// A missing INDEX reads as empty; the repository seeds it from HEAD
+pub fn read_index(path: &Path) -> Result<Index> {
+    Ok(read_index_entries(path)?.map(|(index, _)| index).unwrap_or_default())
+}
+
+/// The staged files and conflicts, or None when there's no index of its own to
+/// read: INDEX is missing, or is an empty one from before it had a version, both
+/// of which mean "same as HEAD". An empty versioned index really is empty.
+pub fn read_index_entries(path: &Path) -> Result<Option<(Index, Conflicts)>> {
+    let stored = match fs::read_to_string(path) {
+        Ok(data) => serde_json::from_str(&data).map_err(|e| SnapError::CorruptIndex(e.to_string()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).at(path),
+    };
+    let entries = match stored {
+        StoredIndex::Versioned(file) if file.version > INDEX_VERSION => {
+            return Err(SnapError::CorruptIndex(format!("unsupported index version {}", file.version)));
+        }
+        StoredIndex::Versioned(file) => file.entries,
+        StoredIndex::Legacy(entries) if entries.is_empty() => return Ok(None),
+        StoredIndex::Legacy(entries) => entries,
+    };
+
+    let mut index = Index::new();
+    let mut conflicts = Conflicts::new();
+    for (file_path, entry) in entries {
+        match entry {
+            IndexEntry::Staged(blob_hash) => {
+                index.insert(file_path, blob_hash);
+            }
+            IndexEntry::Conflicted(versions) => {
+                conflicts.insert(file_path, versions);
+            }
+        }
+    }
+    Ok(Some((index, conflicts)))
+}
+
+pub fn write_index(path: &Path, index: &Index) -> Result<()> {
+    write_index_entries(path, index, &Conflicts::new())
+}
+
+pub fn write_index_entries(path: &Path, index: &Index, conflicts: &Conflicts) -> Result<()> {
+    let mut entries: HashMap<String, IndexEntry> = index.iter().map(|(file_path, hash)| (file_path.clone(), IndexEntry::Staged(hash.clone()))).collect();
+    for (file_path, versions) in conflicts {
+        entries.insert(file_path.clone(), IndexEntry::Conflicted(versions.clone()));
+    }
+    let json = serde_json::to_string(&IndexFile { version: INDEX_VERSION, entries }).expect("the index always serializes");
+    fs::write(path, json).at(path)
+}
+
+// Whether `path` (relative to the repository root) lies inside `dir`
+pub fn in_directory(path: &str, dir: &str) -> bool {
+    let dir = dir.strip_prefix("./").unwrap_or(dir).trim_end_matches('/');
+    dir.is_empty() || dir == "." || path == dir || path.starts_with(&format!("{}/", dir))
+}
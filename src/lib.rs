@@ -0,0 +1,39 @@
+//! Snap, a small version control system.
+//!
+//! The `snap` binary parses arguments and prints what [`Repository`] returns:
+//!
+//! ```no_run
+//! let repo = snap::Repository::discover(std::path::Path::new("."))?;
+//! repo.add("src")?;
+//! if let Some(hash) = repo.commit("Update sources", None)? {
+//!     println!("committed {}", hash);
+//! }
+//! for (hash, commit) in repo.log()? {
+//!     println!("{} {}", &hash[..12], commit.message);
+//! }
+//! # Ok::<(), snap::SnapError>(())
+//! ```
+
+pub mod color;
+pub mod config;
+pub mod diff;
+pub mod error;
+pub mod index;
+pub mod merge;
+pub mod object;
+pub mod pager;
+pub mod patch;
+pub mod rebase;
+pub mod repository;
+pub mod signature;
+
+mod graph;
+mod refs;
+#[cfg(test)]
+mod testutil;
+mod worktree;
+
+pub use error::{Result, SnapError};
+pub use object::{Commit, ObjectKind, Tree, TreeEntry};
+pub use repository::{Checkout, FileStatus, Repository, Status};
+pub use signature::Signature;
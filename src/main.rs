@@ -1,845 +1,1156 @@
-use std::{collections::HashMap, fmt::format, fs, time::{SystemTime, UNIX_EPOCH}};
-use sha256::digest;
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-enum TreeEntry {
-    File { name: String, blob_hash: String}, 
-    Directory { name: String, tree_hash: String}
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use snap::color::{paint, parse_color_when, setup_colors, ColorWhen, Slot};
+use snap::config::{user_config_path, Config, ConfigFile};
+use snap::diff::word::{default_word_regex, word_diff_hunk, WordChunk};
+use snap::diff::{extended_header, file_header, whitespace_errors, Algorithm, DiffLine, DiffOptions, FileDiff, Snapshot, TreeChange};
+use snap::error::{Result, SnapError};
+use snap::index::Stage;
+use snap::merge::{ConflictKind, ConflictStyle, FastForward, MergeConflict, MergeOptions};
+use snap::pager::start_pager;
+use snap::rebase::Rebase;
+use snap::patch::{self, ApplyOptions, ApplyTarget, FilePatch, FileResult, HunkResult};
+use snap::signature::format_date;
+use snap::{Commit, Repository};
+
+fn print_commit_header(hash: &str, commit: &Commit, marker: &str) {
+    println!("{}", paint(Slot::Commit, &format!("commit {}{}", hash, marker)));
+    if commit.is_merge() {
+        let parents: Vec<&str> = commit.parents.iter().map(|parent| &parent[..parent.len().min(12)]).collect();
+        println!("Merge:  {}", parents.join(" "));
+    }
+    if let Some(author) = &commit.author {
+        println!("Author: {} <{}>", author.name, author.email);
+        println!("Date:   {}", format_date(author.timestamp, author.tz_offset));
+    } else {
+        println!("Date:   {}", format_date(commit.timestamp, 0));
+    }
+    if let (Some(author), Some(committer)) = (&commit.author, &commit.committer)
+        && (author.name != committer.name || author.email != committer.email)
+    {
+        println!("Commit: {} <{}>", committer.name, committer.email);
+    }
+    println!("Message: {}", commit.message);
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
-struct Tree {
-    entries: Vec<TreeEntry>,
-}
-#[derive(Serialize, Deserialize, PartialEq)]
-struct Commit {
-    tree_hash: String,
-    parent: String,
-    timestamp: i64,
-    message: String,
-}
+// Prints one file's change as a unified diff with a/ and b/ paths, or with changed
+// words marked inside the lines when `word_diff` is set
+fn show_file_diff(change: &TreeChange, diff: &FileDiff, word_diff: Option<&WordDiff>, algorithm: Algorithm) {
+    let header = file_header(change.old_file_path(), change.new_file_path());
+    println!("{}", paint(Slot::Meta, &header[0]));
+    for line in extended_header(change) {
+        println!("{}", paint(Slot::Meta, &line));
+    }
+    let hunks = match diff {
+        FileDiff::Binary { .. } => {
+            println!("Binary files {} and {} differ", &header[1][4..], &header[2][4..]);
+            return;
+        }
+        FileDiff::Text(hunks) => hunks,
+    };
+    if hunks.is_empty() {
+        return;
+    }
 
-fn save_blob(content: String) -> String{
-    let hash = digest(&content);
-    fs::write(format!(".snap/objects/{}", hash), &content).unwrap();
-    return hash;
+    println!("{}", paint(Slot::Meta, &header[1]));
+    println!("{}", paint(Slot::Meta, &header[2]));
+    for hunk in hunks {
+        println!("{}", paint(Slot::Frag, &hunk.header()));
+        if let Some(word_diff) = word_diff {
+            print_word_diff(&word_diff_hunk(hunk, &word_diff.regex, algorithm), word_diff.mode);
+            continue;
+        }
+        for line in &hunk.lines {
+            match line {
+                DiffLine::Removed(_) => println!("{}", paint(Slot::Old, &line.to_line())),
+                DiffLine::Added(text) => println!("{}", paint_added(text)),
+                _ => println!("{}", line.to_line()),
+            }
+        }
+    }
 }
 
-fn save_tree(tree: Tree) -> String {
-    let json = serde_json::to_string(&tree).unwrap();
-    let tree_hash = digest(&json);
-    fs::write(format!(".snap/objects/{}", tree_hash), json).unwrap();
-    return tree_hash;   
+#[derive(Clone, Copy, PartialEq)]
+enum WordDiffMode {
+    // [-removed-]{+added+}
+    Plain,
+    // Removed words in red and added ones in green, without markers
+    Color,
+    // One chunk per line prefixed with " ", "-" or "+", and "~" for a newline
+    Porcelain,
 }
 
-
-fn save_commit(commit: Commit) {
-    let json = serde_json::to_string(&commit).unwrap();
-    let commit_hash = digest(&json);
-    fs::write(format!(".snap/objects/{}", &commit_hash), json).unwrap();
-
-    // Update branch ref if HEAD is pointing to a branch, otherwise update HEAD directly
-    let head_content = fs::read_to_string(".snap/HEAD").unwrap();
-    let head_content = head_content.trim();
-
-    if head_content.starts_with("ref: ") {
-        // HEAD is pointing to a branch, update the branch ref
-        let branch_path = head_content.strip_prefix("ref: ").unwrap();
-        fs::write(format!(".snap/{}", branch_path), &commit_hash).unwrap();
-    } else {
-        // HEAD is detached (pointing directly to a commit), update HEAD
-        fs::write(".snap/HEAD", &commit_hash).unwrap();
-    }
+struct WordDiff {
+    mode: WordDiffMode,
+    regex: Regex,
 }
 
-fn build_tree(dir: &str) -> String {
-    let mut entries = Vec::new();
-
-    for entry in fs::read_dir(dir).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_file() {
-            let content = fs::read_to_string(&path).unwrap();
-            let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
-            let blob_hash = save_blob(content);
-            entries.push(TreeEntry::File { name: file_name, blob_hash});
-        }
-        else if path.is_dir() {
-            let dir_path = path.to_str().unwrap().to_string();
-            let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
-            let tree_hash = build_tree(&dir_path);
-            entries.push(TreeEntry::Directory { name: file_name, tree_hash })
+fn print_word_diff(chunks: &[WordChunk], mode: WordDiffMode) {
+    let mut out = String::new();
+    for chunk in chunks {
+        match (mode, chunk) {
+            (WordDiffMode::Porcelain, WordChunk::Equal(text)) => out.push_str(&format!(" {}\n", text)),
+            (WordDiffMode::Porcelain, WordChunk::Removed(text)) => out.push_str(&format!("-{}\n", text)),
+            (WordDiffMode::Porcelain, WordChunk::Added(text)) => out.push_str(&format!("+{}\n", text)),
+            (WordDiffMode::Porcelain, WordChunk::Newline) => out.push_str("~\n"),
+            (_, WordChunk::Equal(text)) => out.push_str(text),
+            (WordDiffMode::Plain, WordChunk::Removed(text)) => out.push_str(&format!("[-{}-]", text)),
+            (WordDiffMode::Plain, WordChunk::Added(text)) => out.push_str(&format!("{{+{}+}}", text)),
+            (_, WordChunk::Removed(text)) => out.push_str(&paint(Slot::Old, text)),
+            (_, WordChunk::Added(text)) => out.push_str(&paint(Slot::New, text)),
+            (_, WordChunk::Newline) => out.push('\n'),
         }
     }
-    let tree = Tree {entries};
-    save_tree(tree)
+    print!("{}", out);
 }
 
-fn get_last_commit() -> String {
-    let head = fs::read_to_string(".snap/HEAD").unwrap();
-    let head = head.trim();
-
-    // Check if HEAD is a symbolic ref (pointing to a branch)
-    if head.starts_with("ref: ") {
-        let branch_path = head.strip_prefix("ref: ").unwrap();
-        if let Ok(commit_hash) = fs::read_to_string(format!(".snap/{}", branch_path)) {
-            return commit_hash.trim().to_string();
-        }
+// "+line" in green, with trailing whitespace and spaces before tabs in the indent
+// marked so they stand out
+fn paint_added(text: &str) -> String {
+    let mut out = paint(Slot::New, "+");
+    let mut end = 0;
+    for error in whitespace_errors(text) {
+        out.push_str(&paint(Slot::New, &text[end..error.start]));
+        out.push_str(&paint(Slot::Whitespace, &text[error.clone()]));
+        end = error.end;
     }
+    out.push_str(&paint(Slot::New, &text[end..]));
+    out
+}
+
+const DIFF_USAGE: &str = "usage: diff [--stat | --numstat | --name-only | --name-status] [-U<n>] [--patience | --histogram | --minimal | --diff-algorithm=<name>] [-M[<n>] | -C[<n>] | --no-renames] [--word-diff[=<mode>]] [--word-diff-regex=<regex>] [-w | -b] [--ignore-blank-lines] [--ignore-cr-at-eol] [--staged] [<rev> [<rev>] | <rev>^!] [-- <path>...]";
 
-    return head.to_string();
+#[derive(Clone, Copy, PartialEq)]
+enum DiffFormat {
+    Patch,
+    Stat,
+    NumStat,
+    NameOnly,
+    NameStatus,
 }
 
-// fn log() {
-//     let mut current = get_last_commit();
-    
-//     while !current.is_empty() {
-//         let commit_hash = current.clone();
-//         let commit_data = match fs::read_to_string(format!(".snap/objects/{}", commit_hash)) {
-//             Ok(data) => data,
-//             Err(_) => break,
-//         };
-//         let commit: Commit = match serde_json::from_str(&commit_data) {
-//             Ok(commit) => commit,
-//             Err(_) => break,
-//         };
-        
-//         println!("commit {}", commit_hash);
-//         println!("Message: {}", commit.message);
-//         println!("Timestamp: {}", commit.timestamp);
-//         println!("Files: {:?}", commit.files.keys());
-//         println!();
-        
-//         current = commit.parent;
-//     }
-// }
+// What `diff` was asked to compare and how to show it
+struct DiffArgs {
+    // None unless a format was asked for; `diff` then shows patches and `log` nothing
+    format: Option<DiffFormat>,
+    options: DiffOptions,
+    word_diff: Option<WordDiff>,
+    staged: bool,
+    revisions: Vec<String>,
+    paths: Vec<String>,
+}
 
-fn read_directory(dir: &str, files: &mut HashMap<String, String>) {
-    for entry in fs::read_dir(dir).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_file() {
-            let content = fs::read_to_string(&path).unwrap();
-            let blob_hash = save_blob(content);
-            let file_name = path.to_str().unwrap().to_string();
-            files.insert(file_name, blob_hash);
-        }
-        else if path.is_dir() {
-            read_directory(&path.to_str().unwrap(), files);
-        }
+// A similarity threshold like git's: "75%" is a percentage, bare digits are the
+// fraction after a decimal point (5 is 50%, 90 is 90%), and nothing means 50%
+fn parse_similarity(value: &str) -> Result<u8> {
+    let invalid = || SnapError::InvalidArgument(format!("invalid similarity '{}'", value));
+    if value.is_empty() {
+        return Ok(50);
     }
+    if let Some(percent) = value.strip_suffix('%') {
+        return percent.parse().ok().filter(|&p| p <= 100).ok_or_else(invalid);
+    }
+    if !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let fraction: f64 = format!("0.{}", value).parse().map_err(|_| invalid())?;
+    Ok((fraction * 100.0).round() as u8)
 }
 
-fn diff_fn(tree_hash_1: String, tree_hash_2: String) {
-    compare_trees(tree_hash_1, tree_hash_2);
+fn parse_word_diff_mode(mode: &str) -> Result<WordDiffMode> {
+    match mode {
+        "plain" => Ok(WordDiffMode::Plain),
+        "color" => Ok(WordDiffMode::Color),
+        "porcelain" => Ok(WordDiffMode::Porcelain),
+        _ => Err(SnapError::InvalidArgument(format!("unknown word diff mode '{}' (expected color, plain or porcelain)", mode))),
+    }
 }
 
-fn compare_trees(tree_hash_1: String, tree_hash_2: String) {
-    compare_trees_recursive(tree_hash_1, tree_hash_2, "");
+fn parse_word_regex(regex: &str) -> Result<Regex> {
+    Regex::new(regex).map_err(|e| SnapError::InvalidArgument(format!("invalid word regex '{}': {}", regex, e)))
 }
 
-fn show_file_diff(old_hash: &str, new_hash: &str, file_path: &str) {
-    let old_content = match fs::read_to_string(format!(".snap/objects/{}", old_hash)) {
-        Ok(content) => content,
-        Err(_) => {
-            println!("  Error: Could not read old version");
-            return;
-        }
+// diff.context, diff.algorithm, diff.renames and diff.wordRegex in config set the defaults for the options
+fn parse_diff_args(args: &[String], config: &Config) -> Result<DiffArgs> {
+    let parse_algorithm = |name: &str| {
+        Algorithm::parse(name).ok_or_else(|| SnapError::InvalidArgument(format!("unknown diff algorithm '{}'", name)))
     };
-
-    let new_content = match fs::read_to_string(format!(".snap/objects/{}", new_hash)) {
-        Ok(content) => content,
-        Err(_) => {
-            println!("  Error: Could not read new version");
-            return;
-        }
+    let parse_context = |lines: &str| {
+        lines.parse().map_err(|_| SnapError::InvalidArgument(format!("invalid number of context lines '{}'", lines)))
     };
-
-    let old_lines: Vec<&str> = old_content.lines().collect();
-    let new_lines: Vec<&str> = new_content.lines().collect();
-
-    println!("  --- old/{}", file_path);
-    println!("  +++ new/{}", file_path);
-
-    let mut i = 0;
-    let mut j = 0;
-
-    while i < old_lines.len() || j < new_lines.len() {
-        if i < old_lines.len() && j < new_lines.len() && old_lines[i] == new_lines[j] {
-            // Lines are the same
-            println!("   {}", old_lines[i]);
-            i += 1;
-            j += 1;
-        } else if i < old_lines.len() && (j >= new_lines.len() || !new_lines[j..].contains(&old_lines[i])) {
-            // Line was removed
-            println!("  \x1b[31m - {}\x1b[0m", old_lines[i]);
-            i += 1;
-        } else if j < new_lines.len() {
-            // Line was added
-            println!("  \x1b[32m + {}\x1b[0m", new_lines[j]);
-            j += 1;
-        } else {
-            break;
-        }
+    let mut options = DiffOptions::default();
+    if let Some(name) = config.get("diff.algorithm") {
+        options.algorithm = parse_algorithm(&name)?;
+    }
+    if let Some(lines) = config.get("diff.context") {
+        options.context = parse_context(&lines)?;
+    }
+    match config.get("diff.renames").as_deref() {
+        Some("false" | "no" | "off" | "0") => options.find_renames = None,
+        Some("copies" | "copy") => options.find_copies = Some(50),
+        _ => {}
     }
-    println!();
-}
-
-fn compare_trees_recursive(tree_hash_1: String, tree_hash_2: String, path_prefix: &str) {
-    let tree_data_1 = fs::read_to_string(format!(".snap/objects/{}", tree_hash_1)).unwrap();
-    let tree_1 : Tree = serde_json::from_str(&tree_data_1).unwrap();
-    let tree_data_2 = fs::read_to_string(format!(".snap/objects/{}", tree_hash_2)).unwrap();
-    let tree_2 : Tree = serde_json::from_str(&tree_data_2).unwrap();
 
-    let full_path = |name: &String| -> String {
-        if path_prefix.is_empty() {
-            name.clone()
-        } else {
-            format!("{}/{}", path_prefix, name)
+    let mut parsed = DiffArgs { format: None, options, word_diff: None, staged: false, revisions: Vec::new(), paths: Vec::new() };
+    // A regex alone turns on plain word diffs; a mode alone uses the configured regex
+    let mut word_mode = None;
+    let mut word_regex = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let options = &mut parsed.options;
+        match arg.as_str() {
+            "--" => {
+                parsed.paths.extend(rest.by_ref().cloned());
+            }
+            "--staged" | "--cached" => parsed.staged = true,
+            "-p" | "--patch" => parsed.format = Some(DiffFormat::Patch),
+            "--stat" => parsed.format = Some(DiffFormat::Stat),
+            "--numstat" => parsed.format = Some(DiffFormat::NumStat),
+            "--name-only" => parsed.format = Some(DiffFormat::NameOnly),
+            "--name-status" => parsed.format = Some(DiffFormat::NameStatus),
+            "-U" | "--unified" => {
+                let lines = rest.next().ok_or_else(|| SnapError::Usage(DIFF_USAGE.to_string()))?;
+                options.context = parse_context(lines)?;
+            }
+            arg if arg.starts_with("--unified=") => options.context = parse_context(&arg["--unified=".len()..])?,
+            arg if arg.starts_with("-U") => options.context = parse_context(&arg[2..])?,
+            "--minimal" => options.algorithm = Algorithm::Myers,
+            "--patience" => options.algorithm = Algorithm::Patience,
+            "--histogram" => options.algorithm = Algorithm::Histogram,
+            "--diff-algorithm" => {
+                let name = rest.next().ok_or_else(|| SnapError::Usage(DIFF_USAGE.to_string()))?;
+                options.algorithm = parse_algorithm(name)?;
+            }
+            arg if arg.starts_with("--diff-algorithm=") => options.algorithm = parse_algorithm(&arg["--diff-algorithm=".len()..])?,
+            "-w" | "--ignore-all-space" => options.ignore_all_space = true,
+            "-b" | "--ignore-space-change" => options.ignore_space_change = true,
+            "--ignore-blank-lines" => options.ignore_blank_lines = true,
+            "--ignore-cr-at-eol" => options.ignore_cr_at_eol = true,
+            "--word-diff" => word_mode = Some(WordDiffMode::Plain),
+            arg if arg.starts_with("--word-diff=") => word_mode = Some(parse_word_diff_mode(&arg["--word-diff=".len()..])?),
+            arg if arg.starts_with("--word-diff-regex=") => word_regex = Some(parse_word_regex(&arg["--word-diff-regex=".len()..])?),
+            "--color-words" => word_mode = Some(WordDiffMode::Color),
+            arg if arg.starts_with("--color-words=") => {
+                word_mode = Some(WordDiffMode::Color);
+                word_regex = Some(parse_word_regex(&arg["--color-words=".len()..])?);
+            }
+            "--no-renames" => {
+                options.find_renames = None;
+                options.find_copies = None;
+            }
+            "--find-renames" => options.find_renames = Some(50),
+            arg if arg.starts_with("--find-renames=") => options.find_renames = Some(parse_similarity(&arg["--find-renames=".len()..])?),
+            arg if arg.starts_with("-M") => options.find_renames = Some(parse_similarity(&arg[2..])?),
+            "--find-copies" => options.find_copies = Some(50),
+            arg if arg.starts_with("--find-copies=") => options.find_copies = Some(parse_similarity(&arg["--find-copies=".len()..])?),
+            arg if arg.starts_with("-C") => options.find_copies = Some(parse_similarity(&arg[2..])?),
+            arg if arg.starts_with('-') => return Err(SnapError::Usage(format!("{} (unknown option '{}')", DIFF_USAGE, arg))),
+            _ => parsed.revisions.push(arg.clone()),
         }
-    };
+    }
 
-    // get removed entries only in tree_1
-    for entry1 in &tree_1.entries {
-        let name1 = match entry1 {
-            TreeEntry::File { name, blob_hash: _ } => name,
-            TreeEntry::Directory { name, tree_hash: _ } => name,
+    if word_mode.is_some() || word_regex.is_some() {
+        let regex = match (word_regex, config.get("diff.wordRegex")) {
+            (Some(regex), _) => regex,
+            (None, Some(regex)) => parse_word_regex(&regex)?,
+            (None, None) => default_word_regex(),
         };
-        let found = tree_2.entries.iter().any(|e| {
-            let name2 = match e {
-                TreeEntry::File { name, blob_hash: _ } => name,
-                TreeEntry::Directory { name, tree_hash: _ } => name,
-            };
-            name1 == name2
-        });
-        if !found {
-            println!("Removed: {}", full_path(name1));
-        }
+        parsed.word_diff = Some(WordDiff { mode: word_mode.unwrap_or(WordDiffMode::Plain), regex });
     }
+    Ok(parsed)
+}
 
-    // get added entries only in tree_2
-    for entry2 in &tree_2.entries {
-        let name2 = match entry2 {
-            TreeEntry::File { name, blob_hash: _ } => name,
-            TreeEntry::Directory { name, tree_hash: _ } => name,
-        };
-        let found = tree_1.entries.iter().any(|e| {
-            let name1 = match e {
-                TreeEntry::File { name, blob_hash: _ } => name,
-                TreeEntry::Directory { name, tree_hash: _ } => name,
+// diff               working tree vs index
+// diff --staged      index vs HEAD (or <rev>)
+// diff <rev>         working tree vs <rev>
+// diff <rev> <rev>   one commit vs another
+fn cmd_diff(repo: &Repository, args: &DiffArgs, paths: &[String]) -> Result<()> {
+    let commit_tree = |rev: &str| -> Result<Snapshot> {
+        let hash = repo.resolve_revision(rev)?;
+        Ok(Snapshot::Tree(repo.read_commit(&hash)?.tree_hash))
+    };
+    let (old, new) = match (args.staged, args.revisions.as_slice()) {
+        // <rev>^! is what the commit changed: against its first parent, or everything for a root commit
+        (false, [rev]) if rev.ends_with("^!") => {
+            let commit = repo.read_commit(&repo.resolve_revision(&rev[..rev.len() - 2])?)?;
+            let old = match commit.first_parent() {
+                None => Snapshot::Empty,
+                Some(parent) => Snapshot::Tree(repo.read_commit(parent)?.tree_hash),
             };
-            name1 == name2
-        });
-        if !found {
-            println!("Added: {}", full_path(name2));
+            (old, Snapshot::Tree(commit.tree_hash))
         }
-    }
+        (false, []) => (Snapshot::Index, Snapshot::WorkTree),
+        (true, []) => (repo.head_snapshot()?, Snapshot::Index),
+        (true, [rev]) => (commit_tree(rev)?, Snapshot::Index),
+        (false, [rev]) => (commit_tree(rev)?, Snapshot::WorkTree),
+        (false, [old, new]) => (commit_tree(old)?, commit_tree(new)?),
+        _ => return Err(SnapError::Usage(DIFF_USAGE.to_string())),
+    };
 
-    // get modified entries
-    for entry2 in &tree_2.entries {
-        let name2 = match entry2 {
-            TreeEntry::File { name, blob_hash: _ } => name,
-            TreeEntry::Directory { name, tree_hash: _ } => name,
-        };
-        
-        // get matching entry in tree_1 by name
-        if let Some(entry1) = tree_1.entries.iter().find(|e| {
-            let name1 = match e {
-                TreeEntry::File { name, blob_hash: _ } => name,
-                TreeEntry::Directory { name, tree_hash: _ } => name,
-            };
-            name1 == name2
-        }) {
-            // Both entries exist, compare their hashes
-            let hash1 = match entry1 {
-                TreeEntry::File { name: _, blob_hash } => blob_hash,
-                TreeEntry::Directory { name: _, tree_hash } => tree_hash,
-            };
-            let hash2 = match entry2 {
-                TreeEntry::File { name: _, blob_hash } => blob_hash,
-                TreeEntry::Directory { name: _, tree_hash } => tree_hash,
-            };
-            
-            if hash1 != hash2 {
-                match (entry1, entry2) {
-                    // Both are files - file was modified
-                    (TreeEntry::File { .. }, TreeEntry::File { .. }) => {
-                        println!("Modified: {}", full_path(name2));
-                        show_file_diff(hash1, hash2, &full_path(name2));
-                    }
-                    // Both are directories - recursively compare them
-                    (TreeEntry::Directory { .. }, TreeEntry::Directory { .. }) => {
-                        // recursively comparing the subdirectories to look for change
-                        compare_trees_recursive(hash1.clone(), hash2.clone(), &full_path(name2));
-                    }
-                    // Type changed (file -> directory or vice versa)
-                    _ => {
-                        println!("Type changed: {} (was {:?}, now {:?})", full_path(name2), entry1, entry2);
+    let changes = repo.diff(&old, &new, paths, &args.options)?;
+    print_changes(repo, &changes, &old, &new, args.format.unwrap_or(DiffFormat::Patch), args)
+}
+
+fn print_changes(repo: &Repository, changes: &[TreeChange], old: &Snapshot, new: &Snapshot, format: DiffFormat, args: &DiffArgs) -> Result<()> {
+    let options = &args.options;
+    match format {
+        DiffFormat::NameOnly => {
+            for change in changes {
+                println!("{}", change.path);
+            }
+        }
+        DiffFormat::NameStatus => {
+            for change in changes {
+                println!("{}\t{}", change.kind.status_code(), change.display_path());
+            }
+        }
+        DiffFormat::NumStat | DiffFormat::Stat => {
+            let mut stats = Vec::new();
+            for change in changes {
+                if change.old_blob().is_some() || change.new_blob().is_some() {
+                    stats.push((change.display_path(), repo.diff_file(change, old, new, options)?));
+                }
+            }
+            if format == DiffFormat::Stat {
+                print_stat(&stats);
+            } else {
+                for (path, diff) in &stats {
+                    match diff.line_counts() {
+                        Some((added, removed)) => println!("{}\t{}\t{}", added, removed, path),
+                        None => println!("-\t-\t{}", path),
                     }
                 }
             }
         }
+        DiffFormat::Patch => {
+            for change in changes {
+                if change.old_blob().is_none() && change.new_blob().is_none() {
+                    continue;
+                }
+                let diff = repo.diff_file(change, old, new, options)?;
+                show_file_diff(change, &diff, args.word_diff.as_ref(), options.algorithm);
+            }
+        }
     }
+    Ok(())
 }
 
-
-fn cmd_init() {
-    fs::create_dir_all(".snap/objects").unwrap();
-    fs::create_dir_all(".snap/refs/heads").unwrap();
-    if !fs::metadata(".snap/HEAD").is_ok() {
-        fs::write(".snap/HEAD", "ref: refs/heads/main").unwrap();
-    }
-    println!("Initialized empty repository");
-}
-
-fn cmd_add(directory: &str) {
-    fs::create_dir_all(".snap/objects").unwrap();
-    
-    let mut staged_files: HashMap<String, String> = match fs::read_to_string(".snap/INDEX") {
-        Ok(data) => serde_json::from_str(&data).unwrap_or(HashMap::new()),
-        Err(_) => HashMap::new(),
-    };
-    
-    read_directory_and_stage(directory, &mut staged_files);
-    
-    // Save updated staging area
-    let json = serde_json::to_string(&staged_files).unwrap();
-    fs::write(".snap/INDEX", json).unwrap();
-    
-    println!("Added files from {} to staging area", directory);
-}
-
-fn read_directory_and_stage(dir: &str, staged_files: &mut HashMap<String, String>) {
-    for entry in fs::read_dir(dir).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        
-        if path.to_str().unwrap().contains(".snap") {
-            continue;
+// " path | 12 +++++-----" per file, scaled to fit in 80 columns, then a summary line
+fn print_stat(stats: &[(String, FileDiff)]) {
+    let name_width = stats.iter().map(|(path, _)| path.len()).max().unwrap_or(0);
+    let max_changes = stats.iter().filter_map(|(_, diff)| diff.line_counts()).map(|(a, r)| a + r).max().unwrap_or(0);
+    let count_width = max_changes.to_string().len().max(3);
+    let bar_width = 80usize.saturating_sub(name_width + count_width + 4).max(10);
+    let scale = |n: usize| {
+        if max_changes <= bar_width || n == 0 {
+            n
+        } else {
+            (n * bar_width / max_changes).max(1)
         }
-        
-        if path.is_file() {
-            let content = fs::read_to_string(&path).unwrap();
-            let blob_hash = save_blob(content);  // Save blob to objects/
-            let file_path = path.to_str().unwrap().to_string();
-            staged_files.insert(file_path, blob_hash);
-        } else if path.is_dir() {
-            read_directory_and_stage(path.to_str().unwrap(), staged_files);
+    };
+
+    let (mut insertions, mut deletions) = (0, 0);
+    for (path, diff) in stats {
+        match diff {
+            FileDiff::Binary { old_size, new_size } => {
+                println!(" {:<name_width$} | {:>count_width$} {} -> {} bytes", path, "Bin", old_size, new_size);
+            }
+            FileDiff::Text(_) => {
+                let (added, removed) = diff.line_counts().unwrap_or_default();
+                insertions += added;
+                deletions += removed;
+                let bar = format!("{}{}", paint(Slot::New, &"+".repeat(scale(added))), paint(Slot::Old, &"-".repeat(scale(removed))));
+                println!(" {:<name_width$} | {:>count_width$} {}", path, added + removed, bar);
+            }
         }
     }
-}
 
-fn cmd_commit(message: &str) {
-    let staged_data = fs::read_to_string(".snap/INDEX").unwrap();
-    let staged_files: HashMap<String, String> = serde_json::from_str(&staged_data).unwrap();
-    
-    let tree = Tree {
-        entries: staged_files.into_iter().map(|(name, blob_hash)| {
-            TreeEntry::File { name, blob_hash }
-        }).collect()
-    };
-    
-    let tree_hash = save_tree(tree);
-    
-    let commit = Commit {
-        tree_hash: tree_hash,
-        parent: get_last_commit(),
-        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
-        message: message.to_string() 
-    };
-    
-    save_commit(commit);
-    
-    // Clear staging area after commit
-    fs::write(".snap/INDEX", "{}").unwrap();
-    
-    println!("Commit created: {}", message);
-}
-
-fn cmd_diff() {
-    let current_commit_hash = get_last_commit();
-    
-    // Check if there's a commit to compare
-    if current_commit_hash.is_empty() {
-        println!("No commits to compare");
-        return;
+    let plural = |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let mut summary = format!(" {}", plural(stats.len(), "file changed", "files changed"));
+    if insertions > 0 || deletions == 0 {
+        summary.push_str(&format!(", {}", plural(insertions, "insertion(+)", "insertions(+)")));
     }
-    
-    let current_commit_data = fs::read_to_string(format!(".snap/objects/{}", current_commit_hash)).unwrap();
-    let current_commit: Commit = serde_json::from_str(&current_commit_data).unwrap();
-    let tree_hash_current = current_commit.tree_hash;
-    
-    // Check if there's a parent commit
-    if current_commit.parent.is_empty() {
-        println!("No parent commit to compare with");
-        return;
+    if deletions > 0 || insertions == 0 {
+        summary.push_str(&format!(", {}", plural(deletions, "deletion(-)", "deletions(-)")));
+    }
+    if !stats.is_empty() {
+        println!("{}", summary);
     }
-    
-    let parent_commit_data = fs::read_to_string(format!(".snap/objects/{}", current_commit.parent)).unwrap();
-    let parent_commit: Commit = serde_json::from_str(&parent_commit_data).unwrap();
-    let tree_hash_parent = parent_commit.tree_hash;
-    diff_fn(tree_hash_parent, tree_hash_current);
 }
 
-fn scan_working_directory(dir: &str, files: &mut HashMap<String, String>, skip_snap: bool) {
-    let entries = match fs::read_dir(dir) {
-        Ok(entries) => entries,
-        Err(_) => return,
-    };
+fn cmd_status(repo: &Repository, directory: &str) -> Result<()> {
+    let status = repo.status(directory)?;
 
-    for entry in entries {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
-        let path = entry.path();
-        let path_str = path.to_str().unwrap();
+    match &status.branch {
+        Some(branch) => println!("On branch {}\n", branch),
+        None => println!("HEAD detached at {}\n", &status.head[..status.head.len().min(12)]),
+    }
 
-        if skip_snap && path_str.contains(".snap") {
-            continue;
-        }
+    if status.merging && !status.unmerged.is_empty() {
+        println!("You have unmerged paths.");
+        println!("  (fix conflicts and run \"add <path>\", then \"commit\"; or \"merge --abort\")\n");
+    } else if status.merging {
+        println!("All conflicts fixed but you are still merging.");
+        println!("  (use \"commit\" to conclude the merge)\n");
+    }
+    if status.rebasing && !status.unmerged.is_empty() {
+        println!("You are currently rebasing.");
+        println!("  (fix conflicts and run \"add <path>\", then \"rebase --continue\")");
+        println!("  (use \"rebase --skip\" to drop this commit, or \"rebase --abort\" to go back)\n");
+    } else if status.rebasing {
+        println!("You are currently rebasing.");
+        println!("  (all conflicts fixed: run \"rebase --continue\")\n");
+    }
 
-        if path.is_file() {
-            let content = match fs::read_to_string(&path) {
-                Ok(c) => c,
-                Err(_) => continue,
-            };
-            let blob_hash = digest(&content);
-            // Normalize path by removing leading ./
-            let normalized_path = path_str.strip_prefix("./").unwrap_or(path_str);
-            files.insert(normalized_path.to_string(), blob_hash);
-        } else if path.is_dir() {
-            scan_working_directory(path_str, files, skip_snap);
+    if !status.staged.is_empty() {
+        println!("Changes to be committed:");
+        for (state, file_path) in &status.staged {
+            println!("  {}", paint(Slot::StatusAdded, &format!("{:<12}{}", format!("{}:", state.label()), file_path)));
         }
+        println!();
     }
-}
-
-fn cmd_status(directory: &str) {
-    // Read staged files
-    let staged_files: HashMap<String, String> = match fs::read_to_string(".snap/INDEX") {
-        Ok(data) => serde_json::from_str(&data).unwrap_or(HashMap::new()),
-        Err(_) => HashMap::new(),
-    };
-
-    let mut working_files: HashMap<String, String> = HashMap::new();
-    scan_working_directory(directory, &mut working_files, true);
-
-    println!("On branch main\n");
 
-    // changes to be committed
-    let mut has_staged = false;
-    for (file_path, _staged_hash) in &staged_files {
-        if !has_staged {
-            println!("Changes to be committed:");
-            has_staged = true;
+    if !status.unmerged.is_empty() {
+        println!("Unmerged paths:");
+        for (file_path, versions) in &status.unmerged {
+            println!("  {}", paint(Slot::StatusChanged, &format!("{:<17}{}", format!("{}:", versions.label()), file_path)));
         }
-        println!("  \x1b[32mnew file:   {}\x1b[0m", file_path);
-    }
-    if has_staged {
         println!();
     }
 
-    // Show modified files changes not staged for commit
-    let mut has_modified = false;
-    for (file_path, working_hash) in &working_files {
-        if let Some(staged_hash) = staged_files.get(file_path) {
-            if working_hash != staged_hash {
-                if !has_modified {
-                    println!("Changes not staged for commit:");
-                    has_modified = true;
-                }
-                println!("  \x1b[31mmodified:   {}\x1b[0m", file_path);
-            }
+    if !status.unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        for (state, file_path) in &status.unstaged {
+            println!("  {}", paint(Slot::StatusChanged, &format!("{:<12}{}", format!("{}:", state.label()), file_path)));
         }
-    }
-    if has_modified {
         println!();
     }
 
-    // Show untracked files
-    let mut has_untracked = false;
-    for (file_path, _) in &working_files {
-        if !staged_files.contains_key(file_path) {
-            if !has_untracked {
-                println!("Untracked files:");
-                has_untracked = true;
-            }
-            println!("  \x1b[31m{}\x1b[0m", file_path);
+    if !status.untracked.is_empty() {
+        println!("Untracked files:");
+        for file_path in &status.untracked {
+            println!("  {}", paint(Slot::StatusUntracked, file_path));
         }
-    }
-    if has_untracked {
         println!();
     }
 
-    if !has_staged && !has_modified && !has_untracked {
+    if status.is_clean() {
         println!("nothing to commit, working tree clean");
     }
+    Ok(())
 }
 
-fn cmd_log() {
-    let mut current = get_last_commit();
-
-    if current.is_empty() {
-        println!("No commits yet");
-        return;
+const LOG_USAGE: &str = "usage: log [--all] [-p | --stat | --numstat | --name-only | --name-status] [-m] [-M[<n>] | -C[<n>] | --no-renames]";
+
+// With a format, each commit is followed by its changes against its first parent
+fn cmd_log(repo: &Repository, args: &[String], config: &Config) -> Result<()> {
+    let all = args.iter().any(|arg| arg == "--all");
+    let merge_diffs = args.iter().any(|arg| arg == "-m");
+    let diff_args: Vec<String> = args.iter().filter(|arg| *arg != "--all" && *arg != "-m").cloned().collect();
+    let diff_args = parse_diff_args(&diff_args, config).map_err(|e| match e {
+        SnapError::Usage(_) => SnapError::Usage(LOG_USAGE.to_string()),
+        e => e,
+    })?;
+    if diff_args.staged || !diff_args.revisions.is_empty() || !diff_args.paths.is_empty() {
+        return Err(SnapError::Usage(LOG_USAGE.to_string()));
     }
 
-    println!("Commit history (from current HEAD):\n");
+    let head = repo.head()?;
+    let commits = if all {
+        println!("All commits in repository:\n");
+        repo.log_all()?
+    } else {
+        if head.is_empty() {
+            println!("No commits yet");
+            return Ok(());
+        }
+        println!("Commit history (from current HEAD):\n");
+        repo.log()?
+    };
 
-    while !current.is_empty() {
-        let commit_hash = current.clone();
-        let commit_data = match fs::read_to_string(format!(".snap/objects/{}", commit_hash)) {
-            Ok(data) => data,
-            Err(_) => break,
-        };
-        let commit: Commit = match serde_json::from_str(&commit_data) {
-            Ok(commit) => commit,
-            Err(_) => break,
+    for (hash, commit) in commits {
+        let marker = if hash == head { " (HEAD)" } else { "" };
+        print_commit_header(&hash, &commit, marker);
+        let Some(format) = diff_args.format else {
+            println!();
+            continue;
         };
 
-        let is_head = commit_hash == get_last_commit();
-        let marker = if is_head { " (HEAD)" } else { "" };
-
-        println!("commit {}{}", commit_hash, marker);
-        println!("Message: {}", commit.message);
-        println!("Timestamp: {}", commit.timestamp);
+        // A root commit added everything; a merge is only shown against its parents with -m
+        let new = Snapshot::Tree(commit.tree_hash.clone());
+        if commit.parents.is_empty() {
+            let changes = repo.diff(&Snapshot::Empty, &new, &[], &diff_args.options)?;
+            println!();
+            print_changes(repo, &changes, &Snapshot::Empty, &new, format, &diff_args)?;
+        } else if !commit.is_merge() || merge_diffs {
+            for parent in &commit.parents {
+                let old = Snapshot::Tree(repo.read_commit(parent)?.tree_hash);
+                let changes = repo.diff(&old, &new, &[], &diff_args.options)?;
+                println!();
+                if commit.is_merge() {
+                    println!("(from {})", &parent[..12]);
+                }
+                print_changes(repo, &changes, &old, &new, format, &diff_args)?;
+            }
+        }
         println!();
-
-        current = commit.parent;
     }
+    Ok(())
 }
 
-fn cmd_log_all() {
-    println!("All commits in repository:\n");
+const FORMAT_PATCH_USAGE: &str = "usage: format-patch [-o <dir>] [--stdout] (-<n> | <since> | <rev>..<rev>)";
+
+// One mail per commit (merges are left out), oldest first, written to numbered files or to stdout
+fn cmd_format_patch(repo: &Repository, cwd: &Path, args: &[String], config: &Config) -> Result<()> {
+    let usage = || SnapError::Usage(FORMAT_PATCH_USAGE.to_string());
+    let mut out_dir = cwd.to_path_buf();
+    let mut stdout = false;
+    let mut range = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" | "--output-directory" => out_dir = cwd.join(rest.next().ok_or_else(usage)?),
+            "--stdout" => stdout = true,
+            arg if arg.starts_with('-') && arg.len() > 1 && arg[1..].bytes().all(|b| b.is_ascii_digit()) => range = Some(arg.to_string()),
+            arg if arg.starts_with('-') => return Err(usage()),
+            arg => range = Some(arg.to_string()),
+        }
+    }
+    let range = range.ok_or_else(usage)?;
 
-    let mut commits = Vec::new();
+    let options = parse_diff_args(&[], config)?.options;
+    let mails = repo.format_patches(&range, &options)?;
+    if !stdout {
+        std::fs::create_dir_all(&out_dir).map_err(|source| SnapError::Io { path: out_dir.display().to_string(), source })?;
+    }
+    for (file_name, mail) in mails {
+        if stdout {
+            print!("{}", mail);
+            continue;
+        }
+        let path = out_dir.join(file_name);
+        std::fs::write(&path, mail).map_err(|source| SnapError::Io { path: path.display().to_string(), source })?;
+        println!("{}", path.strip_prefix(cwd).unwrap_or(&path).display());
+    }
+    Ok(())
+}
 
-    // Read all objects and filter for commits
-    if let Ok(entries) = fs::read_dir(".snap/objects") {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let hash = entry.file_name().to_string_lossy().to_string();
-                if let Ok(data) = fs::read_to_string(format!(".snap/objects/{}", hash)) {
-                    if let Ok(commit) = serde_json::from_str::<Commit>(&data) {
-                        commits.push((hash, commit));
+// Reports each hunk that moved or needed fuzz, and each failure; true if all applied
+fn print_apply_results(files: &[FilePatch], results: &[FileResult]) -> bool {
+    for (file, result) in files.iter().zip(results) {
+        if let Some(error) = &result.error {
+            eprintln!("error: {}", error);
+            continue;
+        }
+        for (n, (hunk, outcome)) in file.hunks.iter().zip(&result.hunks).enumerate() {
+            match *outcome {
+                HunkResult::Applied { offset: 0, fuzz: 0 } => {}
+                HunkResult::Applied { offset, fuzz } => {
+                    let mut note = format!("Hunk #{} succeeded at {}", n + 1, (hunk.old_start as isize + offset).max(1));
+                    if offset != 0 {
+                        note.push_str(&format!(" (offset {} line{})", offset, if offset.abs() == 1 { "" } else { "s" }));
+                    }
+                    if fuzz > 0 {
+                        note.push_str(&format!(" with fuzz {}", fuzz));
                     }
+                    println!("{}: {}.", result.path, note);
                 }
+                HunkResult::Rejected => eprintln!("error: patch failed: {}: hunk #{} ({}) does not apply", result.path, n + 1, hunk.header()),
             }
         }
     }
+    results.iter().all(FileResult::is_clean)
+}
 
-    // Sort by timestamp (newest first)
-    commits.sort_by(|a, b| b.1.timestamp.cmp(&a.1.timestamp));
+const APPLY_USAGE: &str = "usage: apply [--check] [--index | --cached] [--reject] [--fuzz=<n>] <patch>...";
 
-    let current_head = get_last_commit();
+fn parse_fuzz(value: &str) -> Result<usize> {
+    value.parse().map_err(|_| SnapError::InvalidArgument(format!("invalid fuzz '{}'", value)))
+}
 
-    for (hash, commit) in commits {
-        let is_head = hash == current_head;
-        let marker = if is_head { " (HEAD)" } else { "" };
+fn cmd_apply(repo: &Repository, cwd: &Path, args: &[String]) -> Result<()> {
+    let mut options = ApplyOptions::default();
+    let mut patch_files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => options.check = true,
+            "--index" => options.target = ApplyTarget::Both,
+            "--cached" => options.target = ApplyTarget::Index,
+            "--reject" => options.reject = true,
+            arg if arg.starts_with("--fuzz=") => options.fuzz = parse_fuzz(&arg["--fuzz=".len()..])?,
+            arg if arg.starts_with('-') => return Err(SnapError::Usage(APPLY_USAGE.to_string())),
+            arg => patch_files.push(cwd.join(arg)),
+        }
+    }
+    if patch_files.is_empty() {
+        return Err(SnapError::Usage(APPLY_USAGE.to_string()));
+    }
 
-        println!("commit {}{}", hash, marker);
-        println!("Message: {}", commit.message);
-        println!("Timestamp: {}", commit.timestamp);
-        println!();
+    let mut failed = false;
+    for path in &patch_files {
+        let text = std::fs::read_to_string(path).map_err(|source| SnapError::Io { path: path.display().to_string(), source })?;
+        let files = patch::parse_diff(&text)?;
+        let results = repo.apply(&files, &options)?;
+        let clean = print_apply_results(&files, &results);
+        if options.reject && !clean {
+            for result in results.iter().filter(|result| result.error.is_none() && !result.is_clean()) {
+                let rejected = result.hunks.iter().filter(|hunk| **hunk == HunkResult::Rejected).count();
+                println!("Applied patch {} with {} reject{} (see {}.rej)", result.path, rejected, if rejected == 1 { "" } else { "s" }, result.path);
+            }
+        }
+        failed |= !clean;
     }
+    if failed {
+        return Err(SnapError::Conflict(if options.reject { "some hunks were rejected" } else { "patch does not apply" }.to_string()));
+    }
+    Ok(())
 }
 
-fn collect_tree_files(tree_hash: &str, base_path: &str, files: &mut std::collections::HashSet<String>) {
-    let tree_data = match fs::read_to_string(format!(".snap/objects/{}", tree_hash)) {
-        Ok(data) => data,
-        Err(_) => return,
-    };
-    let tree: Tree = match serde_json::from_str(&tree_data) {
-        Ok(t) => t,
-        Err(_) => return,
-    };
+// Applies mails from format-patch to the index and working tree and commits each one
+fn cmd_am(repo: &Repository, cwd: &Path, args: &[String]) -> Result<()> {
+    let mut fuzz = 0;
+    let mut mailboxes = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            arg if arg.starts_with("--fuzz=") => fuzz = parse_fuzz(&arg["--fuzz=".len()..])?,
+            arg if arg.starts_with('-') => return Err(SnapError::Usage("usage: am [--fuzz=<n>] <mbox>...".to_string())),
+            arg => mailboxes.push(cwd.join(arg)),
+        }
+    }
+    if mailboxes.is_empty() {
+        return Err(SnapError::Usage("usage: am [--fuzz=<n>] <mbox>...".to_string()));
+    }
 
-    for entry in tree.entries {
-        match entry {
-            TreeEntry::File { name, blob_hash: _ } => {
-                let file_path = if base_path.is_empty() {
-                    name
-                } else {
-                    format!("{}/{}", base_path, name)
-                };
-                files.insert(file_path);
+    for path in &mailboxes {
+        let text = std::fs::read_to_string(path).map_err(|source| SnapError::Io { path: path.display().to_string(), source })?;
+        for mail in patch::split_mailbox(&text) {
+            let mail = patch::parse_mail(mail)?;
+            let subject = mail.message.lines().next().unwrap_or_default().to_string();
+            println!("Applying: {}", subject);
+            let (results, committed) = repo.apply_mail(&mail, fuzz)?;
+            if !print_apply_results(&mail.files, &results) {
+                return Err(SnapError::Conflict(format!("patch failed at '{}'; nothing from it was applied", subject)));
             }
-            TreeEntry::Directory { name, tree_hash } => {
-                let dir_path = if base_path.is_empty() {
-                    name.clone()
-                } else {
-                    format!("{}/{}", base_path, name)
-                };
-                collect_tree_files(&tree_hash, &dir_path, files);
+            if committed.is_none() {
+                println!("Patch is empty, skipped");
             }
         }
     }
+    Ok(())
 }
 
-fn collect_work_directory_files(dir: &str, files: &mut std::collections::HashSet<String>) {
-    let entries = match fs::read_dir(dir) {
-        Ok(entries) => entries,
-        Err(_) => return,
-    };
-
-    for entry in entries {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
-        let path = entry.path();
-        let path_str = path.to_str().unwrap();
-
-        if path_str.contains(".snap") {
-            continue;
-        }
+// Merge settings shared by merge and rebase
+fn merge_options(config: &Config) -> Result<MergeOptions> {
+    let mut options = MergeOptions::default();
+    if let Some(style) = config.get("merge.conflictStyle") {
+        options.style = ConflictStyle::parse(&style).ok_or_else(|| SnapError::InvalidArgument(format!("unknown conflict style '{}'", style)))?;
+    }
+    if let Some(name) = config.get("diff.algorithm") {
+        options.algorithm = Algorithm::parse(&name).ok_or_else(|| SnapError::InvalidArgument(format!("unknown diff algorithm '{}'", name)))?;
+    }
+    Ok(options)
+}
 
-        if path.is_file() {
-            files.insert(path_str.to_string());
-        } else if path.is_dir() {
-            collect_work_directory_files(path_str, files);
+fn print_conflicts(conflicts: &[MergeConflict], theirs: &str) {
+    for conflict in conflicts {
+        let path = &conflict.path;
+        match conflict.kind {
+            ConflictKind::Content => println!("CONFLICT (content): Merge conflict in {}", path),
+            ConflictKind::AddAdd => println!("CONFLICT (add/add): Merge conflict in {}", path),
+            ConflictKind::Binary => println!("CONFLICT (binary): Merge conflict in {}; our version was kept", path),
+            ConflictKind::DeletedByUs => println!("CONFLICT (modify/delete): {} deleted in HEAD and modified in {}", path, theirs),
+            ConflictKind::DeletedByThem => println!("CONFLICT (modify/delete): {} deleted in {} and modified in HEAD", path, theirs),
+            ConflictKind::FileDirectory => println!("CONFLICT (file/directory): the file {} was moved aside to make room for a directory", path),
         }
     }
 }
 
-fn restore_tree(tree_hash: &str, base_path: &str) {
-    let tree_data = fs::read_to_string(format!(".snap/objects/{}", tree_hash)).unwrap();
-    let tree: Tree = serde_json::from_str(&tree_data).unwrap();
+const MERGE_USAGE: &str = "usage: merge [--ff | --ff-only | --no-ff | --squash] [-m <message>] <branch> | merge --abort";
 
-    for entry in tree.entries {
-        match entry {
-            TreeEntry::File { name, blob_hash } => {
-                // Read blob content
-                let content = fs::read_to_string(format!(".snap/objects/{}", blob_hash)).unwrap();
+fn cmd_merge(repo: &Repository, args: &[String], config: &Config) -> Result<()> {
+    let usage = || SnapError::Usage(MERGE_USAGE.to_string());
+    let mut options = merge_options(config)?;
+    if let Some(value) = config.get("merge.ff") {
+        options.fast_forward = FastForward::parse(&value).ok_or_else(|| SnapError::InvalidArgument(format!("invalid merge.ff value '{}'", value)))?;
+    }
+    let mut abort = false;
+    let mut revision = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-m" | "--message" => options.message = Some(rest.next().ok_or_else(usage)?.clone()),
+            "--abort" => abort = true,
+            "--ff" => options.fast_forward = FastForward::Allow,
+            "--ff-only" => options.fast_forward = FastForward::Only,
+            "--no-ff" => options.fast_forward = FastForward::Never,
+            "--squash" => options.squash = true,
+            arg if arg.starts_with('-') => return Err(usage()),
+            _ if revision.is_some() => return Err(usage()),
+            arg => revision = Some(arg),
+        }
+    }
 
-                // Construct full path
-                let file_path = if base_path.is_empty() {
-                    name
-                } else {
-                    format!("{}/{}", base_path, name)
-                };
+    if abort {
+        repo.abort_merge()?;
+        println!("Merge aborted");
+        return Ok(());
+    }
+    let revision = revision.ok_or_else(usage)?;
+    let head = repo.head()?;
+    let merge = repo.merge(revision, &options)?;
+    if merge.up_to_date {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if merge.fast_forward {
+        let target = merge.commit.unwrap_or_default();
+        println!("Updating {}..{}", &head[..12], &target[..12]);
+        println!("Fast-forward");
+        return Ok(());
+    }
 
-                // Create parent directories if needed
-                if let Some(parent) = std::path::Path::new(&file_path).parent() {
-                    fs::create_dir_all(parent).ok();
-                }
+    for path in &merge.merged {
+        println!("Auto-merging {}", path);
+    }
+    print_conflicts(&merge.conflicts, revision);
+    match merge.commit {
+        None if merge.squashed && merge.conflicts.is_empty() => {
+            println!("Squash commit -- not updating HEAD");
+            println!("Automatic merge went well; run \"commit\" to record it");
+            Ok(())
+        }
+        Some(hash) => {
+            println!("Merge made by the three-way strategy.");
+            println!("HEAD is now at {}", &hash[..12]);
+            Ok(())
+        }
+        None => Err(SnapError::Conflict("Automatic merge failed; fix conflicts and then commit the result.".to_string())),
+    }
+}
 
-                // Write file to disk
-                fs::write(&file_path, content).unwrap();
-                println!("Restored: {}", file_path);
-            }
-            TreeEntry::Directory { name, tree_hash } => {
-                // Construct subdirectory path
-                let dir_path = if base_path.is_empty() {
-                    name
-                } else {
-                    format!("{}/{}", base_path, name)
-                };
+const MERGE_BASE_USAGE: &str = "usage: merge-base [--all] <commit> <commit> | merge-base --is-ancestor <commit> <commit>";
 
-                // Create directory
-                fs::create_dir_all(&dir_path).ok();
+// Answers with exit status 1 and no output when there is no merge base or the commit isn't an ancestor
+fn cmd_merge_base(repo: &Repository, args: &[String]) -> Result<()> {
+    let mut all = false;
+    let mut is_ancestor = false;
+    let mut commits = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-a" | "--all" => all = true,
+            "--is-ancestor" => is_ancestor = true,
+            arg if arg.starts_with('-') => return Err(SnapError::Usage(MERGE_BASE_USAGE.to_string())),
+            arg => commits.push(repo.resolve_revision(arg)?),
+        }
+    }
+    let [a, b] = commits.as_slice() else {
+        return Err(SnapError::Usage(MERGE_BASE_USAGE.to_string()));
+    };
+    if is_ancestor && all {
+        return Err(SnapError::Usage(MERGE_BASE_USAGE.to_string()));
+    }
 
-                // Recursively restore subdirectory
-                restore_tree(&tree_hash, &dir_path);
-            }
+    if is_ancestor {
+        if !repo.is_ancestor(a, b)? {
+            std::process::exit(1);
         }
+        return Ok(());
+    }
+    let bases = repo.merge_bases(a, b)?;
+    if bases.is_empty() {
+        std::process::exit(1);
+    }
+    for base in bases.iter().take(if all { bases.len() } else { 1 }) {
+        println!("{}", base);
     }
+    Ok(())
 }
 
-fn cmd_rollback(commit_hash: &str, directory: &str) {
-    let commit_data = match fs::read_to_string(format!(".snap/objects/{}", commit_hash)) {
-        Ok(data) => data,
-        Err(_) => {
-            println!("Error: Commit {} not found", commit_hash);
-            return;
+const REBASE_USAGE: &str = "usage: rebase <upstream> | rebase (--continue | --skip | --abort)";
+
+fn cmd_rebase(repo: &Repository, args: &[String], config: &Config) -> Result<()> {
+    let usage = || SnapError::Usage(REBASE_USAGE.to_string());
+    let options = merge_options(config)?;
+    let rebase = match args {
+        [arg] if arg == "--continue" => repo.rebase_continue(&options)?,
+        [arg] if arg == "--skip" => repo.rebase_skip(&options)?,
+        [arg] if arg == "--abort" => {
+            let head = repo.rebase_abort()?;
+            println!("Rebase aborted; HEAD is back at {}", &head[..12]);
+            return Ok(());
         }
+        [upstream] if !upstream.starts_with('-') => repo.rebase(upstream, &options)?,
+        _ => return Err(usage()),
     };
-    let commit: Commit = match serde_json::from_str(&commit_data) {
-        Ok(c) => c,
-        Err(_) => {
-            println!("Error: Invalid commit data");
-            return;
+    let name = match &rebase.branch {
+        Some(branch) => format!("refs/heads/{}", branch),
+        None => "HEAD".to_string(),
+    };
+    if rebase.up_to_date {
+        println!("Current branch {} is up to date.", rebase.branch.as_deref().unwrap_or("HEAD"));
+        return Ok(());
+    }
+
+    for (hash, new) in &rebase.applied {
+        let subject = repo.read_commit(hash)?.subject().to_string();
+        match new {
+            Some(_) => println!("Applying: {}", subject),
+            None => println!("dropping {} {} -- patch contents already upstream", &hash[..12], subject),
         }
+    }
+    let Rebase { stopped: Some((hash, conflicts)), .. } = &rebase else {
+        println!("Successfully rebased and updated {}.", name);
+        return Ok(());
     };
+    let subject = repo.read_commit(hash)?.subject().to_string();
+    print_conflicts(conflicts, &hash[..12]);
+    Err(SnapError::Conflict(format!(
+        "could not apply {}... {}\nResolve all conflicts, then run \"add <path>\" and \"rebase --continue\".\nRun \"rebase --skip\" to drop this commit, or \"rebase --abort\" to go back to where you started.",
+        &hash[..12],
+        subject
+    )))
+}
+
+fn cmd_rollback(repo: &Repository, revision: &str, directory: &str) -> Result<()> {
+    let commit_hash = repo.resolve_revision(revision)?;
+    let commit = repo.read_commit(&commit_hash)?;
 
     println!("Rolling back to commit: {}", commit.message);
     println!("This will affect files in: {}\n", directory);
 
-    // files existing in target commit
-    let mut target_files = std::collections::HashSet::new();
-    collect_tree_files(&commit.tree_hash, "", &mut target_files);
-
-    // current files in the directory
-    let mut current_files = std::collections::HashSet::new();
-    collect_work_directory_files(directory, &mut current_files);
-
-    // Delete files that exist currently but not in target commit
-    for file in &current_files {
-        // Extract the relative path within the directory
-        let relative_path = file.strip_prefix(&format!("{}/", directory))
-            .or_else(|| file.strip_prefix(directory))
-            .unwrap_or(file);
-
-        if !target_files.contains(relative_path) {
-            match fs::remove_file(file) {
-                Ok(_) => println!("Deleted: {}", file),
-                Err(e) => println!("Warning: Failed to delete {}: {}", file, e),
-            }
-        }
+    let checkout = repo.checkout_commit(&commit_hash, directory)?;
+    for file in &checkout.deleted {
+        println!("Deleted: {}", file);
+    }
+    for (file, reason) in &checkout.failed {
+        println!("Warning: Failed to delete {}: {}", file, reason);
+    }
+    for file in &checkout.restored {
+        println!("Restored: {}", file);
     }
-
-    // Restore all files from target commit
-    restore_tree(&commit.tree_hash, "");
-
-    fs::write(".snap/HEAD", commit_hash).unwrap();
 
     println!("\nRollback complete! HEAD is now at {}", &commit_hash[..12]);
+    Ok(())
 }
 
-fn create_branch(branch_name: String) {
-    let latest_commit = get_last_commit();
-    if fs::exists(format!(".snap/refs/heads/{}", branch_name)).unwrap(){
-        println!("Branch already exists");
-        return;
+fn cmd_switch(repo: &Repository, branch: &str, directory: &str) -> Result<()> {
+    let checkout = repo.checkout_branch(branch, directory)?;
+    for file in &checkout.restored {
+        println!("Restored: {}", file);
     }
-    fs::write(format!(".snap/refs/heads/{}", branch_name), &latest_commit).unwrap();
-    println!("Branch {} created at commit {}", branch_name, &latest_commit[..12]);
+    println!("Switched to branch '{}'", branch);
+    println!("HEAD is now at {}", &checkout.commit[..12]);
+    Ok(())
 }
 
-fn switch_branch(branch_name: String, directory: &str) {
-    let commit_hash = fs::read_to_string(format!(".snap/refs/heads/{}", branch_name)).unwrap();
-    let commit_data = fs::read_to_string(format!(".snap/objects/{}", commit_hash)).unwrap();
-    let commit: Commit = serde_json::from_str(&commit_data).unwrap();
-
-    clear_working_directory(directory);
-
-    restore_tree(&commit.tree_hash, "");
-
-    fs::write(".snap/HEAD", format!("ref: refs/heads/{}", branch_name)).unwrap();
+fn list_branches(repo: &Repository) -> Result<()> {
+    let current_branch = repo.current_branch()?.unwrap_or_default();
+    let branches = repo.branches()?;
 
-    println!("Switched to branch '{}'", branch_name);
-    println!("HEAD is now at {}", &commit_hash[..12]);
+    println!("Branches:");
+    for branch in &branches {
+        let marker = if *branch == current_branch { " *" } else { "  " };
+        println!("{} {}", marker, branch);
+    }
+    if branches.is_empty() {
+        println!("  (no branches yet - create one with 'branch <name>')");
+    }
+    Ok(())
 }
 
-fn list_branches() {
-    let head_content = fs::read_to_string(".snap/HEAD").unwrap_or_default();
-    let current_branch = if head_content.starts_with("ref: refs/heads/") {
-        head_content.trim().strip_prefix("ref: refs/heads/").unwrap_or("")
+fn cmd_config(repo: Option<&Repository>, config: &Config, args: &[String]) -> Result<()> {
+    let mut global = false;
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--global" => global = true,
+            "--local" => global = false,
+            _ => rest.push(arg.as_str()),
+        }
+    }
+
+    let path = if global {
+        user_config_path()
+            .ok_or_else(|| SnapError::InvalidArgument("HOME is not set, so there is no user config file".to_string()))?
     } else {
-        ""
+        repo.map(|repo| repo.snap_dir().join("config")).unwrap_or_default()
     };
 
-    println!("Branches:");
-
-    if let Ok(heads_dir) = fs::read_dir(".snap/refs/heads") {
-        let mut found_branches = false;
-        for entry in heads_dir {
-            if let Ok(entry) = entry {
-                let branch_name = entry.file_name().to_str().unwrap().to_string();
-                let marker = if branch_name == current_branch { " *" } else { "  " };
-                println!("{} {}", marker, branch_name);
-                found_branches = true;
+    match rest.as_slice() {
+        ["get", key] => {
+            let value = if global { ConfigFile::load(path).get(key) } else { config.get(key) };
+            let value = value.ok_or_else(|| SnapError::ConfigKeyNotFound(key.to_string()))?;
+            println!("{}", value);
+        }
+        ["set", key, value] => {
+            if !global && repo.is_none() {
+                return Err(SnapError::NotARepository);
             }
+            let mut file = ConfigFile::load(path);
+            if !file.set(key, value) {
+                return Err(SnapError::InvalidArgument(format!("invalid key '{}' (expected section.name)", key)));
+            }
+            file.save()?;
         }
-        if !found_branches {
-            println!("  (no branches yet - create one with 'branch <name>')");
+        ["unset", key] => {
+            let mut file = ConfigFile::load(path);
+            if !file.unset(key) {
+                return Err(SnapError::ConfigKeyNotFound(key.to_string()));
+            }
+            file.save()?;
+        }
+        ["list"] => {
+            let entries = if global { ConfigFile::load(path).entries() } else { config.list() };
+            for (key, value) in entries {
+                println!("{}={}", key, value);
+            }
+        }
+        _ => {
+            return Err(SnapError::Usage("usage: config [--global] get <key> | set <key> <value> | unset <key> | list".to_string()));
         }
     }
+    Ok(())
 }
 
-fn clear_working_directory(dir: &str) {
-    let entries = match fs::read_dir(dir) {
-        Ok(entries) => entries,
-        Err(_) => return,
-    };
+// Uses the SNAP_DIR/--snap-dir override (whose worktree is the current directory),
+// or walks up from the current directory to the nearest `.snap`
+fn open_repository(cwd: &Path, snap_dir_override: Option<&str>) -> Result<Repository> {
+    match snap_dir_override {
+        Some(dir) => Repository::open(cwd, &cwd.join(dir)),
+        None => Repository::discover(cwd),
+    }
+}
 
-    for entry in entries {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
-        let path = entry.path();
-        let path_str = path.to_str().unwrap();
+const COMMANDS: &[&str] = &[
+    "init", "add", "commit", "diff", "status", "log", "rollback", "branch", "checkout", "switch", "config", "format-patch", "apply", "am", "merge", "merge-base", "rebase",
+];
 
-        if path_str.contains(".snap") {
-            continue;
-        }
+fn main() {
+    // Die quietly like other tools when the reader of our output goes away
+    #[cfg(unix)]
+    // SAFETY: restoring the default action before any output is written
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
 
-        if path.is_file() {
-            fs::remove_file(path).ok();
-        } else if path.is_dir() {
-            clear_working_directory(path_str);
-            fs::remove_dir(path).ok(); // Remove empty directory
+fn run() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // Global options come before the command: -C <path>, --snap-dir <path>,
+    // --color[=<when>], --no-pager
+    let mut snap_dir_override = std::env::var("SNAP_DIR").ok();
+    let mut color = None;
+    let mut no_pager = false;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-C" if i + 1 < args.len() => {
+                std::env::set_current_dir(&args[i + 1]).map_err(|source| SnapError::Io { path: args[i + 1].clone(), source })?;
+                i += 2;
+            }
+            "--snap-dir" if i + 1 < args.len() => {
+                snap_dir_override = Some(args[i + 1].clone());
+                i += 2;
+            }
+            arg if arg.starts_with("--snap-dir=") => {
+                snap_dir_override = Some(arg["--snap-dir=".len()..].to_string());
+                i += 1;
+            }
+            "--no-pager" => {
+                no_pager = true;
+                i += 1;
+            }
+            "--color" => {
+                color = Some(ColorWhen::Always);
+                i += 1;
+            }
+            "--no-color" => {
+                color = Some(ColorWhen::Never);
+                i += 1;
+            }
+            arg if arg.starts_with("--color=") => {
+                color = Some(parse_color_when(&arg["--color=".len()..])?);
+                i += 1;
+            }
+            _ => break,
         }
     }
-}
+    args.drain(1..i);
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    
     if args.len() < 2 {
-        println!("Usage: {} <command> [args]", args[0]);
+        println!("Usage: {} [-C <path>] [--snap-dir <path>] [--color[=<when>]] [--no-pager] <command> [args]", args[0]);
         println!("Commands: init, add <directory>, commit <message>, diff, status");
-        return;
+        return Ok(());
     }
 
+    let cwd: PathBuf = std::env::current_dir().map_err(|source| SnapError::Io { path: ".".to_string(), source })?;
+    if args[1] == "init" {
+        Repository::init(&cwd, snap_dir_override.as_deref().map(Path::new))?;
+        println!("Initialized empty repository");
+        return Ok(());
+    }
+
+    // `config` can still read and write ~/.snapconfig outside a repository
+    let repo = match open_repository(&cwd, snap_dir_override.as_deref()) {
+        Ok(repo) => Some(repo),
+        Err(SnapError::NotARepository) if args[1] == "config" => None,
+        Err(e) => return Err(e),
+    };
+    if let Some(repo) = &repo
+        && repo.migrated_objects() > 0
+    {
+        eprintln!("Migrated {} objects to the fan-out layout", repo.migrated_objects());
+    }
+
+    let config = match &repo {
+        Some(repo) => repo.config(),
+        None => Config::load(None),
+    };
+
+    // alias.<name> = "<command> [args]" expands in place of the alias
+    if !COMMANDS.contains(&args[1].as_str())
+        && let Some(alias) = config.get(&format!("alias.{}", args[1]))
+        && !alias.trim().is_empty()
+    {
+        let expansion: Vec<String> = alias.split_whitespace().map(str::to_string).collect();
+        args.splice(1..2, expansion);
+    }
+
+    // --color can also follow the command, up to a "--"
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let mut kept = Vec::with_capacity(args.len());
+    for (i, arg) in args.drain(..).enumerate() {
+        match arg.as_str() {
+            "--color" if i < end => color = Some(ColorWhen::Always),
+            "--no-color" if i < end => color = Some(ColorWhen::Never),
+            when if i < end && when.starts_with("--color=") => color = Some(parse_color_when(&when["--color=".len()..])?),
+            _ => kept.push(arg),
+        }
+    }
+    args = kept;
+    if args.len() < 2 {
+        return Err(SnapError::Usage(format!("usage: {} [--color[=<when>]] <command> [args]", args[0])));
+    }
+    setup_colors(&config, color)?;
+
+    let Some(repo) = repo else {
+        return match args[1].as_str() {
+            "config" => cmd_config(None, &config, &args[2..]),
+            _ => Err(SnapError::NotARepository),
+        };
+    };
+
+    // Long output from these goes through a pager until `run` returns
+    let _pager = match args[1].as_str() {
+        "log" | "diff" if !no_pager => start_pager(&config, &args[1]),
+        _ => None,
+    };
+
+    let usage = |usage: &str| SnapError::Usage(format!("usage: {} {}", args[0], usage));
+    let repo_path = |arg: &str| -> Result<String> {
+        repo.repo_path(&cwd, arg).ok_or_else(|| SnapError::InvalidArgument(format!("'{}' is outside the repository", arg)))
+    };
+
     match args[1].as_str() {
-        "init" => cmd_init(),
+        "init" => Err(usage("init")),
         "add" => {
-            if args.len() < 3 {
-                println!("Usage: {} add <directory>", args[0]);
-                return;
-            }
-            cmd_add(&args[2]);
+            let path = repo_path(args.get(2).ok_or_else(|| usage("add <path>"))?)?;
+            repo.add(&path)?;
+            println!("Added files from {} to staging area", path);
+            Ok(())
         }
         "commit" => {
-            if args.len() < 3 {
-                println!("Usage: {} commit <message>", args[0]);
-                return;
+            let mut author = None;
+            let mut message = None;
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                if arg == "--author" {
+                    author = rest.next().map(String::as_str);
+                } else if let Some(value) = arg.strip_prefix("--author=") {
+                    author = Some(value);
+                } else {
+                    message = Some(arg.as_str());
+                }
             }
-            cmd_commit(&args[2]);
-        }
-        "diff" => cmd_diff(),
-        "status" => {
-            if args.len() < 3 {
-                println!("Usage: {} status <directory>", args[0]);
-                return;
+            // Finishing a merge can reuse the message the merge prepared
+            let merge_message = if message.is_none() { repo.merge_message() } else { None };
+            let message = message.or(merge_message.as_deref()).ok_or_else(|| usage("commit [--author \"Name <email>\"] <message>"))?;
+            match repo.commit(message, author)? {
+                Some(_) => println!("Commit created: {}", message),
+                None => println!("nothing to commit, working tree clean"),
             }
-            cmd_status(&args[2]);
+            Ok(())
         }
-        "log" => {
-            if args.len() > 2 && args[2] == "--all" {
-                cmd_log_all();
-            } else {
-                cmd_log();
+        "diff" => {
+            let mut diff_args = parse_diff_args(&args[2..], &config)?;
+            // Like git, a name that isn't a revision but exists on disk is a path
+            if diff_args.paths.is_empty() {
+                let (revisions, paths) = diff_args
+                    .revisions
+                    .drain(..)
+                    .partition(|arg| repo.resolve_revision(arg).is_ok() || !cwd.join(arg).exists());
+                diff_args.revisions = revisions;
+                diff_args.paths = paths;
             }
+            let paths = diff_args.paths.iter().map(|path| repo_path(path)).collect::<Result<Vec<_>>>()?;
+            cmd_diff(&repo, &diff_args, &paths)
         }
+        "status" => {
+            // Without a directory the whole worktree is scanned
+            let directory = match args.get(2) {
+                Some(dir) => repo_path(dir)?,
+                None => ".".to_string(),
+            };
+            cmd_status(&repo, &directory)
+        }
+        "log" => cmd_log(&repo, &args[2..], &config),
         "rollback" => {
             if args.len() < 4 {
-                println!("Usage: {} rollback <commit_hash> <directory>", args[0]);
-                println!("Example: {} rollback abc123... test_project", args[0]);
-                return;
+                return Err(usage("rollback <commit_hash> <directory>"));
             }
-            cmd_rollback(&args[2], &args[3]);
+            cmd_rollback(&repo, &args[2], &repo_path(&args[3])?)
         }
-        "branch" => {
-            list_branches();
+        "branch" => list_branches(&repo),
+        "config" => cmd_config(Some(&repo), &config, &args[2..]),
+        "format-patch" => cmd_format_patch(&repo, &cwd, &args[2..], &config),
+        "apply" => cmd_apply(&repo, &cwd, &args[2..]),
+        "am" => cmd_am(&repo, &cwd, &args[2..]),
+        "merge" => cmd_merge(&repo, &args[2..], &config),
+        "merge-base" => cmd_merge_base(&repo, &args[2..]),
+        "rebase" => cmd_rebase(&repo, &args[2..], &config),
+        "checkout" if matches!(args.get(2).map(String::as_str), Some("--ours" | "--theirs")) => {
+            let stage = if args[2] == "--ours" { Stage::Ours } else { Stage::Theirs };
+            if args.len() < 4 {
+                return Err(usage("checkout (--ours | --theirs) <path>..."));
+            }
+            let mut updated = 0;
+            for arg in &args[3..] {
+                updated += repo.checkout_conflict_side(&repo_path(arg)?, stage)?.len();
+            }
+            println!("Updated {} path{} from the index", updated, if updated == 1 { "" } else { "s" });
+            Ok(())
         }
         "checkout" => {
-            create_branch(args[2].clone());
+            let branch = args.get(2).ok_or_else(|| usage("checkout <branch_name>"))?;
+            let commit = repo.create_branch(branch)?;
+            println!("Branch {} created at commit {}", branch, &commit[..12]);
+            Ok(())
         }
         "switch" => {
             if args.len() < 4 {
-                println!("Usage: {} switch <branch_name> <directory>", args[0]);
-                println!("Example: {} switch feature-x test_project", args[0]);
-                return;
+                return Err(usage("switch <branch_name> <directory>"));
             }
-            switch_branch(args[2].clone(), &args[3]);
-        }
-        _ => {
-            println!("Unknown command: {}", args[1]);
-            println!("Commands: init, add <directory>, commit <message>, diff, status, log, rollback <commit_hash> <directory>, branch [name], switch <branch> <directory>");
+            cmd_switch(&repo, &args[2], &repo_path(&args[3])?)
         }
+        _ => Err(SnapError::Usage(format!(
+            "unknown command '{}'\nCommands: init, add <directory>, commit <message>, diff, status, log, rollback <commit_hash> <directory>, branch [name], switch <branch> <directory>, config <get|set|unset|list>, format-patch <range>, apply <patch>, am <mbox>, merge <branch>, merge-base <commit> <commit>, rebase <upstream>",
+            args[1]
+        ))),
     }
 }
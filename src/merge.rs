@@ -0,0 +1,781 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs, io,
+};
+
+use crate::diff::{diff_sequences, Algorithm, ChangeKind, DiffOptions, Edit, Snapshot};
+use crate::error::{IoContext, Result, SnapError};
+use crate::index::{ConflictEntry, Conflicts, Index};
+use crate::object::is_binary;
+use crate::repository::Repository;
+
+/// How overlapping edits are written into a file.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ConflictStyle {
+    // Our lines and theirs
+    #[default]
+    Merge,
+    // Also the lines they both started from, after `|||||||`
+    Diff3,
+}
+
+impl ConflictStyle {
+    pub fn parse(name: &str) -> Option<ConflictStyle> {
+        match name.to_lowercase().as_str() {
+            "merge" => Some(ConflictStyle::Merge),
+            "diff3" => Some(ConflictStyle::Diff3),
+            _ => None,
+        }
+    }
+}
+
+/// The names written after the conflict markers.
+#[derive(Clone, Debug)]
+pub struct MergeLabels {
+    pub base: String,
+    pub ours: String,
+    pub theirs: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConflictKind {
+    // Both sides changed the same lines
+    Content,
+    // Both sides added the file with different contents
+    AddAdd,
+    // We deleted a file they modified
+    DeletedByUs,
+    // They deleted a file we modified
+    DeletedByThem,
+    // Both sides changed a binary file
+    Binary,
+    // One side has a file where the other has a directory; the file was moved aside
+    FileDirectory,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MergeConflict {
+    pub kind: ConflictKind,
+    pub path: String,
+}
+
+/// When `merge` may just move the branch forward instead of creating a merge commit.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum FastForward {
+    // Whenever HEAD is an ancestor of the commit being merged
+    #[default]
+    Allow,
+    // Fail unless HEAD is an ancestor of the commit being merged
+    Only,
+    // Always create a merge commit
+    Never,
+}
+
+impl FastForward {
+    // As in the `merge.ff` setting
+    pub fn parse(value: &str) -> Option<FastForward> {
+        match value.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(FastForward::Allow),
+            "only" => Some(FastForward::Only),
+            "false" | "no" | "off" | "0" => Some(FastForward::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Options for `Repository::merge`.
+#[derive(Clone, Debug, Default)]
+pub struct MergeOptions {
+    // The merge commit's message instead of "Merge branch '<name>'"
+    pub message: Option<String>,
+    pub style: ConflictStyle,
+    pub algorithm: Algorithm,
+    pub fast_forward: FastForward,
+    // Merge the changes into the index and worktree but leave committing them, as one ordinary commit, to the user
+    pub squash: bool,
+}
+
+/// What a merge did.
+#[derive(Debug, Default)]
+pub struct Merge {
+    pub base: String,
+    // The merge commit or, after a fast-forward, the commit HEAD moved to; None when
+    // already up to date, squashing or stopped on conflicts
+    pub commit: Option<String>,
+    pub up_to_date: bool,
+    pub fast_forward: bool,
+    pub squashed: bool,
+    // Files both sides changed that were merged line by line without conflicts
+    pub merged: Vec<String>,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+// The result of merging three sets of files
+pub(crate) struct TreeMerge {
+    index: Index,
+    conflicted: Conflicts,
+    // Conflicted files as written to the worktree, with conflict markers; the
+    // others get our version, or theirs if we deleted them
+    marked: HashMap<String, Vec<u8>>,
+    pub(crate) merged: Vec<String>,
+    pub(crate) conflicts: Vec<MergeConflict>,
+}
+
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+// For every base line, the line of `side` it's kept as, if any
+fn kept_lines(base: &[&[u8]], side: &[&[u8]], algorithm: Algorithm) -> Vec<Option<usize>> {
+    let mut kept = vec![None; base.len()];
+    for edit in diff_sequences(base, side, algorithm) {
+        if let Edit::Equal { old, new } = edit {
+            kept[old] = Some(new);
+        }
+    }
+    kept
+}
+
+// Marker lines always start on a line of their own
+fn push_lines(output: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        output.extend_from_slice(line);
+    }
+    if output.last().is_some_and(|&b| b != b'\n') {
+        output.push(b'\n');
+    }
+}
+
+fn push_conflict(output: &mut Vec<u8>, base: &[&[u8]], ours: &[&[u8]], theirs: &[&[u8]], labels: &MergeLabels, style: ConflictStyle) {
+    // Lines both sides agree on stay outside the markers, unless the base is shown
+    let (mut start, mut end) = (0, 0);
+    if style == ConflictStyle::Merge {
+        while start < ours.len() && start < theirs.len() && ours[start] == theirs[start] {
+            start += 1;
+        }
+        while end < ours.len() - start && end < theirs.len() - start && ours[ours.len() - 1 - end] == theirs[theirs.len() - 1 - end] {
+            end += 1;
+        }
+    }
+    output.extend(ours[..start].concat());
+    output.extend(format!("<<<<<<< {}\n", labels.ours).as_bytes());
+    push_lines(output, &ours[start..ours.len() - end]);
+    if style == ConflictStyle::Diff3 {
+        output.extend(format!("||||||| {}\n", labels.base).as_bytes());
+        push_lines(output, base);
+    }
+    output.extend(b"=======\n");
+    push_lines(output, &theirs[start..theirs.len() - end]);
+    output.extend(format!(">>>>>>> {}\n", labels.theirs).as_bytes());
+    output.extend(ours[ours.len() - end..].concat());
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line. Where
+/// both changed the same lines differently, both versions are kept between
+/// conflict markers. Returns the merged text and the number of conflicts.
+pub fn merge_lines(base: &[u8], ours: &[u8], theirs: &[u8], labels: &MergeLabels, style: ConflictStyle, algorithm: Algorithm) -> (Vec<u8>, usize) {
+    let (base, ours, theirs) = (split_lines(base), split_lines(ours), split_lines(theirs));
+    let in_ours = kept_lines(&base, &ours, algorithm);
+    let in_theirs = kept_lines(&base, &theirs, algorithm);
+
+    let mut output = Vec::new();
+    let mut conflicts = 0;
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        // Lines neither side touched
+        while o < base.len() && in_ours[o] == Some(a) && in_theirs[o] == Some(b) {
+            output.extend_from_slice(base[o]);
+            (o, a, b) = (o + 1, a + 1, b + 1);
+        }
+
+        // Everything up to the next base line both sides kept was changed by at least one of them
+        let next = (o..base.len()).find_map(|i| Some((i, in_ours[i]?, in_theirs[i]?)));
+        let (end_o, end_a, end_b) = next.unwrap_or((base.len(), ours.len(), theirs.len()));
+        let (base_chunk, ours_chunk, theirs_chunk) = (&base[o..end_o], &ours[a..end_a], &theirs[b..end_b]);
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            output.extend(theirs_chunk.concat());
+        } else if theirs_chunk == base_chunk {
+            output.extend(ours_chunk.concat());
+        } else {
+            push_conflict(&mut output, base_chunk, ours_chunk, theirs_chunk, labels, style);
+            conflicts += 1;
+        }
+
+        if next.is_none() {
+            break;
+        }
+        (o, a, b) = (end_o, end_a, end_b);
+    }
+    (output, conflicts)
+}
+
+impl Repository {
+    /// The commit being merged while a merge waits for its conflicts to be resolved.
+    pub fn merge_head(&self) -> Result<Option<String>> {
+        let path = self.snap_path("MERGE_HEAD");
+        match fs::read_to_string(&path) {
+            Ok(hash) => Ok(Some(hash.trim().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).at(&path),
+        }
+    }
+
+    /// The message prepared for the commit that finishes a merge or squash merge.
+    pub fn merge_message(&self) -> Option<String> {
+        fs::read_to_string(self.snap_path("MERGE_MSG")).or_else(|_| fs::read_to_string(self.snap_path("SQUASH_MSG"))).ok()
+    }
+
+    pub(crate) fn clear_merge_state(&self) -> Result<()> {
+        for name in ["MERGE_HEAD", "MERGE_MSG", "SQUASH_MSG"] {
+            let path = self.snap_path(name);
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e).at(&path),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives up an unfinished merge or squash merge, putting the index and the
+    /// tracked files back as they are in HEAD.
+    pub fn abort_merge(&self) -> Result<()> {
+        // A squash merge leaves no MERGE_HEAD, only the message for its commit
+        if self.merge_head()?.is_none() && !self.snap_path("SQUASH_MSG").is_file() {
+            return Err(SnapError::Conflict("there is no merge to abort (MERGE_HEAD missing)".to_string()));
+        }
+        self.reset_hard(&self.head()?)?;
+        self.clear_merge_state()
+    }
+
+    // Puts the index and every tracked file back as they are in `commit_hash`
+    pub(crate) fn reset_hard(&self, commit_hash: &str) -> Result<()> {
+        let tree_hash = self.read_commit(commit_hash)?.tree_hash;
+        let files = self.tree_files(&tree_hash)?;
+        for path in self.tracked_files()?.keys() {
+            if !files.contains_key(path) {
+                self.remove_work_file(path)?;
+            }
+        }
+        for (path, blob_hash) in &files {
+            self.write_work_file(path, &self.objects().read_blob(blob_hash)?)?;
+        }
+        self.reset_index(&tree_hash)
+    }
+
+    /// Merges `revision` into HEAD. If HEAD is an ancestor of it, the branch is
+    /// just moved forward, unless `options` says otherwise. Changes made on only
+    /// one side since the merge base are taken as they are, files changed on both
+    /// are merged line by line, and renames on either side are followed. Without
+    /// conflicts the result is committed with both commits as parents; otherwise
+    /// the index and worktree are left for the conflicts to be resolved and
+    /// `commit` to finish the merge. A squash merge never commits, and the commit
+    /// that finishes it has a single parent.
+    pub fn merge(&self, revision: &str, options: &MergeOptions) -> Result<Merge> {
+        if self.merge_head()?.is_some() {
+            return Err(SnapError::Conflict("you have not concluded your merge (MERGE_HEAD exists); commit the result or abort it".to_string()));
+        }
+        if options.squash && options.fast_forward == FastForward::Never {
+            return Err(SnapError::InvalidArgument("--squash and --no-ff can't be combined".to_string()));
+        }
+        let ours = self.head()?;
+        if ours.is_empty() {
+            return Err(SnapError::BadRevision("HEAD".to_string()));
+        }
+        let theirs = self.resolve_revision(revision)?;
+        let base = self.merge_base(&ours, &theirs)?.ok_or_else(|| SnapError::Conflict("refusing to merge unrelated histories".to_string()))?;
+        if base == theirs {
+            return Ok(Merge { base, up_to_date: true, ..Merge::default() });
+        }
+
+        let can_fast_forward = base == ours;
+        if !can_fast_forward && options.fast_forward == FastForward::Only {
+            return Err(SnapError::Conflict("not possible to fast-forward, aborting".to_string()));
+        }
+        let status = self.status(".")?;
+        if !status.staged.is_empty() || !status.unstaged.is_empty() || !status.unmerged.is_empty() {
+            return Err(SnapError::Conflict("you have local changes; commit them before merging".to_string()));
+        }
+
+        if can_fast_forward && !options.squash && options.fast_forward != FastForward::Never {
+            let head_files = self.commit_files(&ours)?;
+            let target_files = self.commit_files(&theirs)?;
+            self.update_work_tree(&head_files, &target_files, &HashMap::new())?;
+            self.reset_index(&self.read_commit(&theirs)?.tree_hash)?;
+            self.update_head(&theirs)?;
+            return Ok(Merge { base, commit: Some(theirs), fast_forward: true, ..Merge::default() });
+        }
+
+        let labels = MergeLabels { base: base[..12].to_string(), ours: "HEAD".to_string(), theirs: revision.to_string() };
+        let tree_of = |hash: &str| -> Result<String> { Ok(self.read_commit(hash)?.tree_hash) };
+        let result = self.merge_into_work_tree(&tree_of(&base)?, &tree_of(&ours)?, &tree_of(&theirs)?, &labels, options)?;
+
+        if options.squash {
+            let mut message = "Squashed commit of the following:\n".to_string();
+            for (hash, commit) in self.commits_between(&ours, &theirs)? {
+                message.push_str(&format!("\ncommit {}\n\n    {}\n", hash, commit.message.trim_end().replace('\n', "\n    ")));
+            }
+            let squash_msg_path = self.snap_path("SQUASH_MSG");
+            fs::write(&squash_msg_path, &message).at(&squash_msg_path)?;
+            return Ok(Merge { base, squashed: true, merged: result.merged, conflicts: result.conflicts, ..Merge::default() });
+        }
+
+        let message = match &options.message {
+            Some(message) => message.clone(),
+            None if self.branch_commit(revision)?.is_some() => format!("Merge branch '{}'", revision),
+            None => format!("Merge commit '{}'", revision),
+        };
+        let merge_head_path = self.snap_path("MERGE_HEAD");
+        fs::write(&merge_head_path, &theirs).at(&merge_head_path)?;
+        let merge_msg_path = self.snap_path("MERGE_MSG");
+        fs::write(&merge_msg_path, &message).at(&merge_msg_path)?;
+
+        let commit = if result.conflicts.is_empty() { self.commit(&message, None)? } else { None };
+        Ok(Merge { base, commit, merged: result.merged, conflicts: result.conflicts, ..Merge::default() })
+    }
+
+    pub(crate) fn commit_files(&self, hash: &str) -> Result<Index> {
+        self.tree_files(&self.read_commit(hash)?.tree_hash)
+    }
+
+    fn tree_files(&self, tree_hash: &str) -> Result<Index> {
+        let mut files = Index::new();
+        self.collect_tree_blobs(tree_hash, "", &mut files)?;
+        Ok(files)
+    }
+
+    // Merges the changes from the `base` tree to `theirs` into the index and
+    // worktree, which must match `ours`, recording conflicts in the index
+    pub(crate) fn merge_into_work_tree(&self, base_tree: &str, our_tree: &str, their_tree: &str, labels: &MergeLabels, options: &MergeOptions) -> Result<TreeMerge> {
+        let head_files = self.tree_files(our_tree)?;
+        let (mut base_files, mut our_files, mut their_files) = (self.tree_files(base_tree)?, head_files.clone(), self.tree_files(their_tree)?);
+        self.follow_renames(base_tree, our_tree, their_tree, &mut base_files, &mut our_files, &mut their_files)?;
+
+        let result = self.merge_files(&base_files, &our_files, &their_files, labels, options)?;
+
+        // Conflicted files start out as our version, or theirs if we deleted them
+        let mut work_files = result.index.clone();
+        for (path, entry) in &result.conflicted {
+            if let Some(blob_hash) = entry.ours.as_ref().or(entry.theirs.as_ref()) {
+                work_files.insert(path.clone(), blob_hash.clone());
+            }
+        }
+        self.update_work_tree(&head_files, &work_files, &result.marked)?;
+        self.write_index_with_conflicts(&result.index, &result.conflicted)?;
+        Ok(result)
+    }
+
+    // Turns the tracked files from `from` into `to`, writing `marked` contents in
+    // place of their blobs. Nothing is touched if an untracked file is in the way.
+    pub(crate) fn update_work_tree(&self, from: &Index, to: &Index, marked: &HashMap<String, Vec<u8>>) -> Result<()> {
+        for path in to.keys() {
+            if !from.contains_key(path) && self.untracked_in_the_way(path, from) {
+                return Err(SnapError::Conflict(format!("untracked working tree file '{}' would be overwritten by merge", path)));
+            }
+        }
+
+        for path in from.keys() {
+            if !to.contains_key(path) {
+                self.remove_work_file(path)?;
+            }
+        }
+        for (path, blob_hash) in to {
+            if let Some(content) = marked.get(path) {
+                self.write_work_file(path, content)?;
+            } else if from.get(path) != Some(blob_hash) {
+                self.write_work_file(path, &self.objects().read_blob(blob_hash)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Whether writing the new file `path` would clobber something untracked: a file
+    // at its place or at one of its parent directories, or a directory there that
+    // isn't just tracked files about to be removed
+    fn untracked_in_the_way(&self, path: &str, from: &Index) -> bool {
+        let parents = path.match_indices('/').map(|(i, _)| &path[..i]);
+        if parents.filter(|parent| !from.contains_key(*parent)).any(|parent| self.work_path(parent).is_file()) {
+            return true;
+        }
+        match fs::symlink_metadata(self.work_path(path)) {
+            Err(_) => false,
+            Ok(metadata) if metadata.is_dir() => {
+                let files = self.list_files(path);
+                files.is_empty() || files.iter().any(|file| !from.contains_key(file))
+            }
+            Ok(_) => true,
+        }
+    }
+
+    // A file one side renamed and the other still has under its old name is moved
+    // to the new name on the other side too, so edits to it meet in one place
+    fn follow_renames(&self, base_tree: &str, our_tree: &str, their_tree: &str, base: &mut Index, ours: &mut Index, theirs: &mut Index) -> Result<()> {
+        let renames = |tree: &str| -> Result<Vec<(String, String)>> {
+            let changes = self.diff(&Snapshot::Tree(base_tree.to_string()), &Snapshot::Tree(tree.to_string()), &[], &DiffOptions::default())?;
+            Ok(changes
+                .into_iter()
+                .filter(|change| matches!(change.kind, ChangeKind::Renamed(_)))
+                .filter_map(|change| Some((change.old_path?, change.path)))
+                .collect())
+        };
+        let (our_renames, their_renames) = (renames(our_tree)?, renames(their_tree)?);
+
+        for (renames, other_renames, other) in [(&our_renames, &their_renames, &mut *theirs), (&their_renames, &our_renames, &mut *ours)] {
+            for (from, to) in renames {
+                // Renamed on both sides: each keeps its own name
+                if other_renames.iter().any(|(other_from, _)| other_from == from) || other.contains_key(to) {
+                    continue;
+                }
+                if let Some(blob_hash) = other.remove(from) {
+                    other.insert(to.clone(), blob_hash);
+                    if let Some(blob_hash) = base.remove(from) {
+                        base.insert(to.clone(), blob_hash);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_files(&self, base: &Index, ours: &Index, theirs: &Index, labels: &MergeLabels, options: &MergeOptions) -> Result<TreeMerge> {
+        let mut result = TreeMerge { index: Index::new(), conflicted: Conflicts::new(), marked: HashMap::new(), merged: Vec::new(), conflicts: Vec::new() };
+        let paths: BTreeSet<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+
+        for path in paths {
+            let (o, a, b) = (base.get(path), ours.get(path), theirs.get(path));
+            // Taken as is when only one side changed it, or both the same way
+            let resolved = if a == b || b == o {
+                Some(a)
+            } else if a == o {
+                Some(b)
+            } else {
+                None
+            };
+            if let Some(side) = resolved {
+                if let Some(blob_hash) = side {
+                    result.index.insert(path.clone(), blob_hash.clone());
+                }
+                continue;
+            }
+
+            let conflict = |kind| MergeConflict { kind, path: path.clone() };
+            let versions = ConflictEntry { base: o.cloned(), ours: a.cloned(), theirs: b.cloned() };
+            let (a, b) = match (a, b) {
+                (Some(a), Some(b)) => (a, b),
+                (Some(_), None) => {
+                    result.conflicted.insert(path.clone(), versions);
+                    result.conflicts.push(conflict(ConflictKind::DeletedByThem));
+                    continue;
+                }
+                (None, Some(_)) => {
+                    result.conflicted.insert(path.clone(), versions);
+                    result.conflicts.push(conflict(ConflictKind::DeletedByUs));
+                    continue;
+                }
+                (None, None) => continue,
+            };
+
+            let base_content = match o {
+                Some(o) => self.objects().read_blob(o)?,
+                None => Vec::new(),
+            };
+            let (our_content, their_content) = (self.objects().read_blob(a)?, self.objects().read_blob(b)?);
+            if is_binary(&base_content) || is_binary(&our_content) || is_binary(&their_content) {
+                result.conflicted.insert(path.clone(), versions);
+                result.conflicts.push(conflict(ConflictKind::Binary));
+                continue;
+            }
+
+            let (merged, conflicts) = merge_lines(&base_content, &our_content, &their_content, labels, options.style, options.algorithm);
+            if conflicts == 0 {
+                result.index.insert(path.clone(), self.objects().save_blob(&merged)?);
+                result.merged.push(path.clone());
+            } else {
+                result.conflicted.insert(path.clone(), versions);
+                result.marked.insert(path.clone(), merged);
+                result.conflicts.push(conflict(if o.is_some() { ConflictKind::Content } else { ConflictKind::AddAdd }));
+            }
+        }
+
+        // A file can't stay where the other side now has a directory. It's moved
+        // aside and left conflicted, as the version of the side that had it.
+        let paths: Vec<String> = result.index.keys().chain(result.conflicted.keys()).cloned().collect();
+        let directories: HashSet<&str> = paths.iter().flat_map(|path| path.match_indices('/').map(move |(i, _)| &path[..i])).collect();
+        for path in paths.iter().filter(|path| directories.contains(path.as_str())) {
+            let ours_has_it = ours.contains_key(path);
+            let side = if ours_has_it { &labels.ours } else { &labels.theirs };
+            let moved = format!("{}~{}", path, side.replace('/', "_"));
+            let mut versions = result.conflicted.remove(path).unwrap_or_default();
+            if let Some(blob_hash) = result.index.remove(path) {
+                versions = if ours_has_it {
+                    ConflictEntry { ours: Some(blob_hash), ..ConflictEntry::default() }
+                } else {
+                    ConflictEntry { theirs: Some(blob_hash), ..ConflictEntry::default() }
+                };
+            }
+            result.conflicted.insert(moved.clone(), versions);
+            if let Some(content) = result.marked.remove(path) {
+                result.marked.insert(moved, content);
+            }
+            result.conflicts.push(MergeConflict { kind: ConflictKind::FileDirectory, path: path.clone() });
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestRepo;
+
+    fn labels() -> MergeLabels {
+        MergeLabels { base: "base".to_string(), ours: "HEAD".to_string(), theirs: "topic".to_string() }
+    }
+
+    fn merge(base: &str, ours: &str, theirs: &str, style: ConflictStyle) -> (String, usize) {
+        let (merged, conflicts) = merge_lines(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), &labels(), style, Algorithm::Myers);
+        (String::from_utf8(merged).unwrap(), conflicts)
+    }
+
+    #[test]
+    fn separate_edits_merge_cleanly() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let ours = "ONE\ntwo\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\nsix\n";
+        assert_eq!(merge(base, ours, theirs, ConflictStyle::Merge), ("ONE\ntwo\nthree\nfour\nFIVE\nsix\n".to_string(), 0));
+    }
+
+    #[test]
+    fn same_edit_on_both_sides_is_not_a_conflict() {
+        let base = "a\nb\nc\n";
+        let both = "a\nB\nc\n";
+        assert_eq!(merge(base, both, both, ConflictStyle::Merge), (both.to_string(), 0));
+    }
+
+    #[test]
+    fn overlapping_edits_conflict() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nours\nc\n";
+        let theirs = "a\ntheirs\nc\n";
+        let expected = "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\nc\n";
+        assert_eq!(merge(base, ours, theirs, ConflictStyle::Merge), (expected.to_string(), 1));
+    }
+
+    #[test]
+    fn lines_both_sides_add_stay_outside_the_markers() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nsame\nours\nc\n";
+        let theirs = "a\nsame\ntheirs\nc\n";
+        let expected = "a\nsame\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\nc\n";
+        assert_eq!(merge(base, ours, theirs, ConflictStyle::Merge), (expected.to_string(), 1));
+    }
+
+    #[test]
+    fn deleting_lines_the_other_side_modified_conflicts() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nc\n";
+        let theirs = "a\nB\nc\n";
+        let expected = "a\n<<<<<<< HEAD\n=======\nB\n>>>>>>> topic\nc\n";
+        assert_eq!(merge(base, ours, theirs, ConflictStyle::Merge), (expected.to_string(), 1));
+    }
+
+    #[test]
+    fn diff3_style_shows_the_base() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nours\nc\n";
+        let theirs = "a\ntheirs\nc\n";
+        let expected = "a\n<<<<<<< HEAD\nours\n||||||| base\nb\n=======\ntheirs\n>>>>>>> topic\nc\n";
+        assert_eq!(merge(base, ours, theirs, ConflictStyle::Diff3), (expected.to_string(), 1));
+    }
+
+    #[test]
+    fn conflict_markers_start_on_their_own_line() {
+        let (merged, conflicts) = merge("a\n", "ours", "theirs", ConflictStyle::Merge);
+        assert_eq!((merged.as_str(), conflicts), ("<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\n", 1));
+    }
+
+    // main keeps `d/f` and changes `a`; topic replaces the directory `d` with a file
+    fn directory_becomes_file(name: &str) -> TestRepo {
+        let repo = TestRepo::new(name);
+        repo.write("a", "a\n");
+        repo.write("d/f", "f\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.remove("d");
+        repo.write("d", "now a file\n");
+        repo.commit_all("d is a file");
+        repo.switch(&main);
+        repo
+    }
+
+    #[test]
+    fn merge_replaces_a_tracked_directory_with_a_file() {
+        let repo = directory_becomes_file("merge-dir-to-file");
+        repo.write("a", "A\n");
+        repo.commit_all("change a");
+
+        let merge = repo.merge("topic", &MergeOptions::default()).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert!(merge.commit.is_some());
+        assert_eq!(repo.read("d").as_deref(), Some("now a file\n"));
+        assert_eq!(repo.read("a").as_deref(), Some("A\n"));
+    }
+
+    #[test]
+    fn untracked_files_in_the_way_stop_a_merge() {
+        let repo = TestRepo::new("merge-untracked");
+        repo.write("a", "a\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.write("new", "theirs\n");
+        repo.commit_all("add new");
+        repo.switch(&main);
+        repo.write("a", "A\n");
+        repo.commit_all("change a");
+
+        // An untracked file where theirs goes
+        repo.write("new", "mine\n");
+        assert!(matches!(repo.merge("topic", &MergeOptions::default()), Err(SnapError::Conflict(_))));
+        assert_eq!(repo.read("new").as_deref(), Some("mine\n"));
+
+        // An untracked directory there
+        repo.remove("new");
+        repo.write("new/mine", "mine\n");
+        assert!(matches!(repo.merge("topic", &MergeOptions::default()), Err(SnapError::Conflict(_))));
+        assert_eq!(repo.read("new/mine").as_deref(), Some("mine\n"));
+    }
+
+    fn options(fast_forward: FastForward, squash: bool) -> MergeOptions {
+        MergeOptions { fast_forward, squash, ..MergeOptions::default() }
+    }
+
+    #[test]
+    fn ff_only_moves_the_branch_over_a_directory_turned_file() {
+        let repo = directory_becomes_file("merge-ff-dir-to-file");
+        let topic = repo.branch_commit("topic").unwrap().unwrap();
+
+        let merge = repo.merge("topic", &options(FastForward::Only, false)).unwrap();
+        assert!(merge.fast_forward);
+        assert_eq!(repo.head().unwrap(), topic);
+        assert_eq!(repo.branch_commit(&repo.current_branch()).unwrap(), Some(topic));
+        assert_eq!(repo.read("d").as_deref(), Some("now a file\n"));
+        assert!(repo.status(".").unwrap().unstaged.is_empty());
+    }
+
+    // main stays at "base" while topic gains one commit, or main also moves on
+    fn forked(name: &str, main_moves: bool) -> (TestRepo, String) {
+        let repo = TestRepo::new(name);
+        repo.write("a", "a\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.write("b", "b\n");
+        repo.commit_all("add b");
+        repo.switch(&main);
+        if main_moves {
+            repo.write("c", "c\n");
+            repo.commit_all("add c");
+        }
+        (repo, main)
+    }
+
+    #[test]
+    fn fast_forward_unless_told_otherwise() {
+        let (repo, _) = forked("merge-ff", false);
+        let base = repo.head().unwrap();
+        let merge = repo.merge("topic", &MergeOptions::default()).unwrap();
+        assert!(merge.fast_forward);
+        assert_eq!(repo.read_commit(&repo.head().unwrap()).unwrap().parents, vec![base.clone()]);
+
+        let merge = repo.merge("topic", &MergeOptions::default()).unwrap();
+        assert!(merge.up_to_date);
+    }
+
+    #[test]
+    fn no_ff_records_a_merge_commit() {
+        let (repo, _) = forked("merge-no-ff", false);
+        let base = repo.head().unwrap();
+        let topic = repo.branch_commit("topic").unwrap().unwrap();
+        let merge = repo.merge("topic", &options(FastForward::Never, false)).unwrap();
+        assert!(!merge.fast_forward);
+        let commit = repo.read_commit(&merge.commit.unwrap()).unwrap();
+        assert_eq!(commit.parents, vec![base, topic]);
+        assert_eq!(repo.read("b").as_deref(), Some("b\n"));
+    }
+
+    #[test]
+    fn ff_only_refuses_diverged_branches() {
+        let (repo, _) = forked("merge-ff-only", true);
+        let head = repo.head().unwrap();
+        assert!(matches!(repo.merge("topic", &options(FastForward::Only, false)), Err(SnapError::Conflict(_))));
+        assert_eq!(repo.head().unwrap(), head);
+        assert_eq!(repo.read("b"), None);
+    }
+
+    #[test]
+    fn squash_stages_the_changes_for_a_single_parent_commit() {
+        let (repo, _) = forked("merge-squash", true);
+        let head = repo.head().unwrap();
+        let merge = repo.merge("topic", &options(FastForward::Allow, true)).unwrap();
+        assert!(merge.squashed && merge.commit.is_none());
+        assert_eq!(repo.head().unwrap(), head);
+        assert_eq!(repo.read("b").as_deref(), Some("b\n"));
+        assert!(repo.merge_message().unwrap().starts_with("Squashed commit of the following:"));
+
+        let commit = repo.commit(&repo.merge_message().unwrap(), None).unwrap().unwrap();
+        assert_eq!(repo.read_commit(&commit).unwrap().parents, vec![head]);
+        assert_eq!(repo.merge_message(), None);
+    }
+
+    #[test]
+    fn abort_undoes_a_conflicted_merge_and_squash() {
+        for squash in [false, true] {
+            let repo = TestRepo::new(if squash { "merge-abort-squash" } else { "merge-abort" });
+            repo.write("a", "a\n");
+            repo.commit_all("base");
+            let main = repo.current_branch();
+            repo.branch("topic");
+            repo.write("a", "theirs\n");
+            repo.write("b", "b\n");
+            repo.commit_all("topic");
+            repo.switch(&main);
+            repo.write("a", "ours\n");
+            let head = repo.commit_all("main");
+
+            let merge = repo.merge("topic", &options(FastForward::Allow, squash)).unwrap();
+            assert_eq!(merge.conflicts.len(), 1);
+            assert_eq!(repo.conflicts().unwrap().len(), 1);
+
+            repo.abort_merge().unwrap();
+            assert_eq!(repo.head().unwrap(), head);
+            assert_eq!(repo.read("a").as_deref(), Some("ours\n"));
+            assert_eq!(repo.read("b"), None);
+            assert!(repo.conflicts().unwrap().is_empty());
+            assert!(repo.abort_merge().is_err());
+        }
+    }
+
+    #[test]
+    fn merge_replaces_a_tracked_file_with_a_directory() {
+        let repo = TestRepo::new("merge-file-to-dir");
+        repo.write("a", "a\n");
+        repo.write("d", "a file\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.remove("d");
+        repo.write("d/f", "f\n");
+        let topic = repo.commit_all("d is a directory");
+        let mut paths: Vec<_> = repo.commit_files(&topic).unwrap().into_keys().collect();
+        paths.sort();
+        assert_eq!(paths, ["a", "d/f"]);
+        repo.switch(&main);
+        repo.write("a", "A\n");
+        repo.commit_all("change a");
+
+        let merge = repo.merge("topic", &MergeOptions::default()).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(repo.read("d/f").as_deref(), Some("f\n"));
+    }
+}
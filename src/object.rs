@@ -0,0 +1,360 @@
+use std::{
+    fs,
+    io::{self, BufRead, Read},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use sha256::digest;
+
+use crate::error::{IoContext, Result, SnapError};
+use crate::signature::Signature;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum TreeEntry {
+    File { name: String, blob_hash: String },
+    Directory { name: String, tree_hash: String },
+}
+
+impl TreeEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            TreeEntry::File { name, .. } | TreeEntry::Directory { name, .. } => name,
+        }
+    }
+
+    pub fn hash(&self) -> &str {
+        match self {
+            TreeEntry::File { blob_hash, .. } => blob_hash,
+            TreeEntry::Directory { tree_hash, .. } => tree_hash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(from = "StoredCommit")]
+pub struct Commit {
+    pub tree_hash: String,
+    // First parent first; none for a root commit, several for a merge
+    pub parents: Vec<String>,
+    pub timestamp: i64,
+    pub message: String,
+    // Commits written before identities were recorded have neither field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<Signature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committer: Option<Signature>,
+}
+
+impl Commit {
+    /// The parent a branch was on before this commit, or None for a root commit.
+    pub fn first_parent(&self) -> Option<&str> {
+        self.parents.first().map(String::as_str)
+    }
+
+    pub fn is_merge(&self) -> bool {
+        self.parents.len() > 1
+    }
+
+    // The first line of the message
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+}
+
+// Commits as stored: older ones have a single `parent` (empty for a root commit),
+// and merges from before `parents` add the merged commits as `other_parents`
+#[derive(Deserialize)]
+struct StoredCommit {
+    tree_hash: String,
+    #[serde(default)]
+    parents: Vec<String>,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    other_parents: Vec<String>,
+    timestamp: i64,
+    message: String,
+    #[serde(default)]
+    author: Option<Signature>,
+    #[serde(default)]
+    committer: Option<Signature>,
+}
+
+impl From<StoredCommit> for Commit {
+    fn from(stored: StoredCommit) -> Commit {
+        let mut parents = stored.parents;
+        parents.extend(stored.parent.filter(|parent| !parent.is_empty()));
+        parents.extend(stored.other_parents);
+        Commit {
+            tree_hash: stored.tree_hash,
+            parents,
+            timestamp: stored.timestamp,
+            message: stored.message,
+            author: stored.author,
+            committer: stored.committer,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+}
+
+impl ObjectKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+        }
+    }
+
+    pub fn parse(tag: &str) -> Option<ObjectKind> {
+        match tag {
+            "blob" => Some(ObjectKind::Blob),
+            "tree" => Some(ObjectKind::Tree),
+            "commit" => Some(ObjectKind::Commit),
+            _ => None,
+        }
+    }
+}
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// Objects are stored as zstd("<kind> <len>\0<payload>")
+fn encode_object(kind: ObjectKind, payload: &[u8]) -> Vec<u8> {
+    let mut data = format!("{} {}\0", kind.as_str(), payload.len()).into_bytes();
+    data.extend_from_slice(payload);
+    data
+}
+
+// An object's id is the digest of its payload alone, as it was before objects had
+// a header, so content already in a repository keeps its id
+pub fn hash_object(payload: &[u8]) -> String {
+    digest(payload)
+}
+
+// Treat content as binary the same way git does: a NUL byte near the start.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+fn invalid_object(hash: &str, reason: &str) -> SnapError {
+    SnapError::CorruptObject { hash: hash.to_string(), reason: reason.to_string() }
+}
+
+fn parse_object_header(hash: &str, data: &[u8]) -> Result<(ObjectKind, usize, usize)> {
+    let nul = data.iter().position(|&b| b == 0).ok_or_else(|| invalid_object(hash, "missing header"))?;
+    let header = std::str::from_utf8(&data[..nul]).map_err(|_| invalid_object(hash, "bad header"))?;
+    let (tag, len) = header.split_once(' ').ok_or_else(|| invalid_object(hash, "bad header"))?;
+    let kind = ObjectKind::parse(tag).ok_or_else(|| invalid_object(hash, "unknown object type"))?;
+    let len = len.parse().map_err(|_| invalid_object(hash, "bad length"))?;
+    Ok((kind, len, nul + 1))
+}
+
+pub fn is_object_hash(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// Objects written before the typed format were raw content or bare JSON
+fn legacy_object_kind(raw: &[u8]) -> ObjectKind {
+    if serde_json::from_slice::<Commit>(raw).is_ok() {
+        ObjectKind::Commit
+    } else if serde_json::from_slice::<Tree>(raw).is_ok() {
+        ObjectKind::Tree
+    } else {
+        ObjectKind::Blob
+    }
+}
+
+/// The `.snap/objects` directory.
+pub struct ObjectStore {
+    dir: PathBuf,
+}
+
+impl ObjectStore {
+    pub fn new(dir: PathBuf) -> ObjectStore {
+        ObjectStore { dir }
+    }
+
+    // Objects are sharded by the first two hex characters: objects/7c/373cd2...
+    fn object_path(&self, hash: &str) -> PathBuf {
+        match (hash.get(..2), hash.get(2..)) {
+            (Some(dir), Some(rest)) if !rest.is_empty() => self.dir.join(dir).join(rest),
+            _ => self.dir.join(hash),
+        }
+    }
+
+    /// Moves objects from the flat layout used by older repositories into fan-out
+    /// directories, returning how many were moved.
+    pub fn migrate_flat(&self) -> Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(0),
+        };
+
+        let mut migrated = 0;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.path().is_file() || !is_object_hash(&name) {
+                continue;
+            }
+            let shard = self.dir.join(&name[..2]);
+            fs::create_dir_all(&shard).at(&shard)?;
+            fs::rename(entry.path(), self.object_path(&name)).at(entry.path())?;
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        let mut hashes = Vec::new();
+        let shards = match fs::read_dir(&self.dir) {
+            Ok(shards) => shards,
+            Err(_) => return hashes,
+        };
+
+        for shard in shards.flatten() {
+            let prefix = shard.file_name().to_string_lossy().to_string();
+            if let Ok(entries) = fs::read_dir(shard.path()) {
+                for entry in entries.flatten() {
+                    let hash = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+                    if is_object_hash(&hash) {
+                        hashes.push(hash);
+                    }
+                }
+            }
+        }
+        hashes
+    }
+
+    pub fn write(&self, kind: ObjectKind, payload: &[u8]) -> Result<String> {
+        let hash = hash_object(payload);
+        let path = self.object_path(&hash);
+        // Objects are content addressed, so an existing file already holds these bytes.
+        // The id doesn't cover the kind, though, so a blob whose content is a tree's
+        // JSON would share its id; refuse that rather than read one back as the other
+        if fs::metadata(&path).is_ok() {
+            let existing = self.read_kind(&hash)?;
+            if existing != kind {
+                return Err(SnapError::Conflict(format!("object {} already exists as a {}, not a {}", hash, existing.as_str(), kind.as_str())));
+            }
+            return Ok(hash);
+        }
+        let shard = self.dir.join(&hash[..2]);
+        fs::create_dir_all(&shard).at(&shard)?;
+        let compressed = zstd::encode_all(&encode_object(kind, payload)[..], 0).at(&path)?;
+        fs::write(&path, compressed).at(&path)?;
+        Ok(hash)
+    }
+
+    pub fn read(&self, hash: &str) -> Result<(ObjectKind, Vec<u8>)> {
+        let path = self.object_path(hash);
+        let raw = match fs::read(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(invalid_object(hash, "object not found")),
+            Err(e) => return Err(e).at(&path),
+        };
+        if !raw.starts_with(&ZSTD_MAGIC) {
+            return Ok((legacy_object_kind(&raw), raw));
+        }
+
+        let data = zstd::decode_all(&raw[..]).map_err(|e| invalid_object(hash, &e.to_string()))?;
+        let (kind, len, start) = parse_object_header(hash, &data)?;
+        if data.len() - start != len {
+            return Err(invalid_object(hash, "length mismatch"));
+        }
+        Ok((kind, data[start..].to_vec()))
+    }
+
+    // Reads just enough of the object to know its kind, without inflating the payload
+    pub fn read_kind(&self, hash: &str) -> Result<ObjectKind> {
+        let path = self.object_path(hash);
+        let mut reader = io::BufReader::new(fs::File::open(&path).at(&path)?);
+        if !reader.fill_buf().at(&path)?.starts_with(&ZSTD_MAGIC) {
+            return self.read(hash).map(|(kind, _)| kind);
+        }
+
+        let mut decoder = zstd::stream::read::Decoder::with_buffer(reader).at(&path)?;
+        let mut header = [0u8; 32];
+        let mut filled = 0;
+        while filled < header.len() && !header[..filled].contains(&0) {
+            let n = decoder.read(&mut header[filled..]).map_err(|e| invalid_object(hash, &e.to_string()))?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        parse_object_header(hash, &header[..filled]).map(|(kind, _, _)| kind)
+    }
+
+    fn read_typed(&self, hash: &str, expected: ObjectKind) -> Result<Vec<u8>> {
+        let (kind, payload) = self.read(hash)?;
+        if kind != expected {
+            return Err(invalid_object(hash, &format!("expected {}, found {}", expected.as_str(), kind.as_str())));
+        }
+        Ok(payload)
+    }
+
+    pub fn read_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        self.read_typed(hash, ObjectKind::Blob)
+    }
+
+    pub fn read_tree(&self, hash: &str) -> Result<Tree> {
+        let payload = self.read_typed(hash, ObjectKind::Tree)?;
+        serde_json::from_slice(&payload).map_err(|e| invalid_object(hash, &e.to_string()))
+    }
+
+    pub fn read_commit(&self, hash: &str) -> Result<Commit> {
+        let payload = self.read_typed(hash, ObjectKind::Commit)?;
+        serde_json::from_slice(&payload).map_err(|e| invalid_object(hash, &e.to_string()))
+    }
+
+    pub fn save_blob(&self, content: &[u8]) -> Result<String> {
+        self.write(ObjectKind::Blob, content)
+    }
+
+    pub fn save_tree(&self, tree: &Tree) -> Result<String> {
+        let json = serde_json::to_string(tree).expect("trees always serialize");
+        self.write(ObjectKind::Tree, json.as_bytes())
+    }
+
+    pub fn save_commit(&self, commit: &Commit) -> Result<String> {
+        let json = serde_json::to_string(commit).expect("commits always serialize");
+        self.write(ObjectKind::Commit, json.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestRepo;
+
+    #[test]
+    fn same_payload_same_kind_is_stored_once() {
+        let repo = TestRepo::new("objects-dedup");
+        let first = repo.objects().save_blob(b"hello\n").unwrap();
+        assert_eq!(repo.objects().save_blob(b"hello\n").unwrap(), first);
+        assert_eq!(first, hash_object(b"hello\n"));
+        assert_eq!(repo.objects().read_blob(&first).unwrap(), b"hello\n");
+    }
+
+    #[test]
+    fn a_blob_cannot_take_over_a_tree_with_the_same_payload() {
+        let repo = TestRepo::new("objects-kind");
+        let tree = Tree { entries: vec![TreeEntry::File { name: "a".to_string(), blob_hash: hash_object(b"a\n") }] };
+        let hash = repo.objects().save_tree(&tree).unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(matches!(repo.objects().save_blob(json.as_bytes()), Err(SnapError::Conflict(_))));
+        assert_eq!(repo.objects().read_tree(&hash).unwrap(), tree);
+    }
+}
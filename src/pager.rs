@@ -0,0 +1,58 @@
+use std::io::{IsTerminal, Write};
+
+use crate::config::Config;
+
+/// A pager reading our stdout. Dropping it closes the pipe and waits for the user
+/// to quit the pager, so the prompt doesn't come back underneath it.
+pub struct Pager(std::process::Child);
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        let _ = std::io::stdout().flush();
+        #[cfg(unix)]
+        // SAFETY: nothing writes to stdout after this
+        unsafe {
+            libc::close(1);
+        }
+        let _ = self.0.wait();
+    }
+}
+
+/// Sends the rest of stdout through $SNAP_PAGER, core.pager, $PAGER or `less`
+/// when it is a terminal; `pager.<command> = false` turns this off per command
+#[cfg(unix)]
+pub fn start_pager(config: &Config, command: &str) -> Option<Pager> {
+    use std::os::fd::AsRawFd;
+
+    let disabled = config.get(&format!("pager.{}", command)).is_some_and(|value| matches!(value.as_str(), "false" | "no" | "off" | "0"));
+    if disabled || !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let pager = std::env::var("SNAP_PAGER")
+        .ok()
+        .or_else(|| config.get("core.pager"))
+        .or_else(|| std::env::var("PAGER").ok())
+        .unwrap_or_else(|| "less".to_string());
+    if pager.trim().is_empty() || pager.trim() == "cat" {
+        return None;
+    }
+
+    let mut command = std::process::Command::new("sh");
+    command.arg("-c").arg(&pager).stdin(std::process::Stdio::piped());
+    // Quit at once on short output, pass colours through, leave the screen as it was
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = command.spawn().ok()?;
+    let stdin = child.stdin.take()?;
+    // SAFETY: fd 1 is replaced by the pipe before anything else is written to it
+    unsafe {
+        libc::dup2(stdin.as_raw_fd(), 1);
+    }
+    Some(Pager(child))
+}
+
+#[cfg(not(unix))]
+pub fn start_pager(_config: &Config, _command: &str) -> Option<Pager> {
+    None
+}
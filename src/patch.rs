@@ -0,0 +1,725 @@
+use std::fs;
+
+use crate::diff::{self, DiffLine, DiffOptions, Hunk, Snapshot};
+use crate::error::{IoContext, Result, SnapError};
+use crate::index::Index;
+use crate::object::Commit;
+use crate::repository::Repository;
+use crate::signature::{format_rfc2822_date, parse_date, parse_identity, Signature};
+
+// The fixed date on each mail's "From <hash>" line, as in git's mailboxes
+const MAIL_MARKER: &str = " Mon Sep 17 00:00:00 2001";
+
+/// One file's changes in a patch. No old path means the file is created, no new
+/// path that it is deleted; different paths mean it was renamed or copied.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    // The old file stays where it is (a copy rather than a rename)
+    pub copy: bool,
+    // Binary changes are only named in a patch, so they can't be applied
+    pub binary: bool,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    pub fn path(&self) -> &str {
+        self.new_path.as_deref().or(self.old_path.as_deref()).unwrap_or_default()
+    }
+}
+
+/// A commit read back from a mail written by `format-patch`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MailPatch {
+    pub author: Option<Signature>,
+    pub message: String,
+    pub files: Vec<FilePatch>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HunkResult {
+    // `offset` lines away from where the hunk said, ignoring `fuzz` lines of context
+    Applied { offset: isize, fuzz: usize },
+    Rejected,
+}
+
+/// How one file of a patch went.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileResult {
+    pub path: String,
+    pub hunks: Vec<HunkResult>,
+    // A problem with the file as a whole, e.g. it is missing
+    pub error: Option<String>,
+}
+
+impl FileResult {
+    pub fn is_clean(&self) -> bool {
+        self.error.is_none() && !self.hunks.contains(&HunkResult::Rejected)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ApplyTarget {
+    WorkTree,
+    // The index only (`--cached`)
+    Index,
+    // Both, reading from the working tree (`--index`)
+    Both,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApplyOptions {
+    pub target: ApplyTarget,
+    // Lines of context that may be ignored at each end of a hunk that doesn't match
+    pub fuzz: usize,
+    // Apply what fits and write the rest to <file>.rej instead of applying nothing
+    pub reject: bool,
+    // Only report whether the patch applies
+    pub check: bool,
+}
+
+impl Default for ApplyOptions {
+    fn default() -> ApplyOptions {
+        ApplyOptions { target: ApplyTarget::WorkTree, fuzz: 2, reject: false, check: false }
+    }
+}
+
+// "@@ -a,b +c,d @@", where a missing count means 1
+fn parse_hunk_header(line: &str) -> Option<Hunk> {
+    let ranges = line.strip_prefix("@@ -")?;
+    let (ranges, _) = ranges.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let ((old_start, old_lines), (new_start, new_lines)) = (range(old)?, range(new)?);
+    Some(Hunk { old_start, old_lines, new_start, new_lines, lines: Vec::new() })
+}
+
+// Splits on "\n" only, so lines of CRLF files keep their "\r"
+fn split_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split_inclusive('\n').map(|line| line.strip_suffix('\n').unwrap_or(line))
+}
+
+// A path named by a patch must stay inside the worktree and out of the repository
+fn checked_path(path: &str) -> Result<String> {
+    if path.is_empty() || path.starts_with('/') || path.split('/').any(|part| part == ".." || part == ".snap") {
+        return Err(SnapError::InvalidArgument(format!("invalid path '{}' in patch", path)));
+    }
+    Ok(path.to_string())
+}
+
+// "a/path" or "b/path" to "path", and /dev/null to None
+fn patch_path(name: &str) -> Result<Option<String>> {
+    let name = name.split('\t').next().unwrap_or(name).trim_end();
+    if name == "/dev/null" {
+        return Ok(None);
+    }
+    let name = name.strip_prefix("a/").or_else(|| name.strip_prefix("b/")).unwrap_or(name);
+    checked_path(name).map(Some)
+}
+
+/// Reads the files of a unified diff, with or without `diff --git` headers.
+pub fn parse_diff(text: &str) -> Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut lines = split_lines(text).peekable();
+    // A plain "---" starts a new file unless it follows this file's own header
+    let mut in_header = false;
+
+    while let Some(line) = lines.next() {
+        // Only the lines of a hunk keep a "\r"; headers are read without it
+        let line = line.trim_end_matches('\r');
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            let (old, new) = paths.rsplit_once(" b/").unwrap_or((paths, paths));
+            files.push(FilePatch { old_path: patch_path(old)?, new_path: patch_path(new)?, ..FilePatch::default() });
+            in_header = true;
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("--- ")
+            && lines.peek().is_some_and(|next| next.starts_with("+++ "))
+        {
+            if !in_header {
+                files.push(FilePatch::default());
+            }
+            let new_name = &lines.next().unwrap_or_default()["+++ ".len()..];
+            let file = files.last_mut().expect("a file was just pushed");
+            (file.old_path, file.new_path) = (patch_path(name)?, patch_path(new_name)?);
+            in_header = false;
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            continue;
+        };
+
+        if let Some(mut hunk) = parse_hunk_header(line) {
+            in_header = false;
+            let (mut old_left, mut new_left) = (hunk.old_lines, hunk.new_lines);
+            while old_left > 0 || new_left > 0 || lines.peek().is_some_and(|next| next.starts_with('\\')) {
+                let Some(line) = lines.next() else {
+                    return Err(SnapError::InvalidArgument(format!("patch ends in the middle of a hunk for '{}'", file.path())));
+                };
+                let (marker, text) = line.split_at(line.len().min(1));
+                let text = text.to_string();
+                match marker {
+                    // Some mailers drop the space of an empty context line
+                    " " | "" => {
+                        old_left = old_left.saturating_sub(1);
+                        new_left = new_left.saturating_sub(1);
+                        hunk.lines.push(DiffLine::Context(text));
+                    }
+                    "-" => {
+                        old_left = old_left.saturating_sub(1);
+                        hunk.lines.push(DiffLine::Removed(text));
+                    }
+                    "+" => {
+                        new_left = new_left.saturating_sub(1);
+                        hunk.lines.push(DiffLine::Added(text));
+                    }
+                    "\\" => hunk.lines.push(DiffLine::NoNewline),
+                    _ => return Err(SnapError::InvalidArgument(format!("corrupt patch line: {}", line))),
+                }
+            }
+            file.hunks.push(hunk);
+        } else if let Some(path) = line.strip_prefix("rename from ").or_else(|| line.strip_prefix("copy from ")) {
+            file.old_path = Some(checked_path(path)?);
+            file.copy = line.starts_with("copy");
+        } else if let Some(path) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+            file.new_path = Some(checked_path(path)?);
+        } else if line.starts_with("new file mode") {
+            file.old_path = None;
+        } else if line.starts_with("deleted file mode") {
+            file.new_path = None;
+        } else if line.starts_with("Binary files ") {
+            file.binary = true;
+        }
+    }
+    Ok(files)
+}
+
+/// Splits a mailbox into the mails in it, each starting with a "From <hash>" line.
+pub fn split_mailbox(text: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.starts_with("From ") && line.trim_end().ends_with(MAIL_MARKER) {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    starts.push(text.len());
+    starts.windows(2).map(|w| &text[w[0]..w[1]]).filter(|mail| !mail.trim().is_empty()).collect()
+}
+
+/// Reads one mail: the From/Date/Subject headers, the message up to "---", and the diff.
+pub fn parse_mail(mail: &str) -> Result<MailPatch> {
+    let mut name_email = None;
+    let mut date = None;
+    let mut subject = String::new();
+    let mut lines = split_lines(mail);
+    for line in lines.by_ref() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("From: ") {
+            name_email = parse_identity(value);
+        } else if let Some(value) = line.strip_prefix("Date: ") {
+            date = Some(parse_date(value)?);
+        } else if let Some(value) = line.strip_prefix("Subject: ") {
+            subject = value.to_string();
+        }
+    }
+    // "[PATCH 2/5] Fix things" -> "Fix things"
+    if subject.starts_with('[')
+        && let Some((_, rest)) = subject.split_once("] ")
+    {
+        subject = rest.to_string();
+    }
+
+    let mut body = Vec::new();
+    let mut diff_text = String::new();
+    for line in lines.by_ref() {
+        if line.trim_end_matches('\r') == "---" || line.starts_with("diff --git ") {
+            diff_text.push_str(line);
+            diff_text.push('\n');
+            break;
+        }
+        body.push(line.trim_end_matches('\r'));
+    }
+    for line in lines {
+        // The signature separator ends the patch
+        if line.trim_end_matches('\r') == "-- " {
+            break;
+        }
+        diff_text.push_str(line);
+        diff_text.push('\n');
+    }
+
+    let body = body.join("\n");
+    let message = match body.trim() {
+        "" => subject,
+        body => format!("{}\n\n{}", subject, body),
+    };
+    if message.is_empty() {
+        return Err(SnapError::InvalidArgument("patch has no subject".to_string()));
+    }
+    let author = name_email.map(|(name, email)| {
+        let (timestamp, tz_offset) = date.unwrap_or_default();
+        Signature { name, email, timestamp, tz_offset }
+    });
+    Ok(MailPatch { author, message, files: parse_diff(&diff_text)? })
+}
+
+// A hunk's old and new lines, each with its line ending as in the file
+fn hunk_sides(hunk: &Hunk) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    let (mut old, mut new) = (Vec::new(), Vec::new());
+    // Which sides the previous line went to, for a following "\ No newline"
+    let mut last = (false, false);
+    for line in &hunk.lines {
+        let text = |text: &str| format!("{}\n", text).into_bytes();
+        match line {
+            DiffLine::Context(line) => {
+                old.push(text(line));
+                new.push(text(line));
+                last = (true, true);
+            }
+            DiffLine::Removed(line) => {
+                old.push(text(line));
+                last = (true, false);
+            }
+            DiffLine::Added(line) => {
+                new.push(text(line));
+                last = (false, true);
+            }
+            DiffLine::NoNewline => {
+                for (side, touched) in [(&mut old, last.0), (&mut new, last.1)] {
+                    if touched && let Some(line) = side.last_mut() {
+                        line.pop();
+                    }
+                }
+            }
+        }
+    }
+    (old, new)
+}
+
+// How many context lines a hunk starts and ends with
+fn context_ends(hunk: &Hunk) -> (usize, usize) {
+    let is_context = |line: &&DiffLine| matches!(line, DiffLine::Context(_));
+    let leading = hunk.lines.iter().take_while(is_context).count();
+    let trailing = hunk.lines.iter().rev().filter(|line| **line != DiffLine::NoNewline).take_while(is_context).count();
+    (leading, trailing)
+}
+
+// Where a hunk's old lines begin, counting from 0. An empty old side names the
+// line it comes after rather than its first line.
+fn start_of(hunk: &Hunk) -> usize {
+    if hunk.old_lines == 0 { hunk.old_start } else { hunk.old_start.saturating_sub(1) }
+}
+
+/// Applies hunks to a file's content. Each hunk is looked for where it says,
+/// then at growing offsets, then with up to `fuzz` context lines dropped from
+/// each end. Returns the new content and what happened to each hunk.
+pub fn apply_hunks(content: &[u8], hunks: &[Hunk], fuzz: usize) -> (Vec<u8>, Vec<HunkResult>) {
+    let mut lines: Vec<Vec<u8>> = content.split_inclusive(|&b| b == b'\n').map(<[u8]>::to_vec).collect();
+    let mut results = Vec::new();
+    // Lines added minus lines removed by the hunks so far, and how far from their
+    // stated place those hunks were found
+    let (mut growth, mut drift): (isize, isize) = (0, 0);
+    // Hunks may not overlap lines an earlier hunk produced
+    let mut floor = 0;
+
+    for hunk in hunks {
+        let (old, new) = hunk_sides(hunk);
+        let (leading, trailing) = context_ends(hunk);
+        let mut applied = None;
+
+        for f in 0..=fuzz {
+            let (head, tail) = (f.min(leading), f.min(trailing));
+            if f > 0 && (head == 0 && tail == 0 || head + tail >= old.len()) {
+                break;
+            }
+            let old = &old[head..old.len() - tail];
+            let expected = (start_of(hunk) + head) as isize + growth + drift;
+            if let Some(at) = find_lines(&lines, old, expected, floor) {
+                applied = Some((at, head, tail, f));
+                break;
+            }
+        }
+
+        match applied {
+            Some((at, head, tail, f)) => {
+                let old_len = old.len() - head - tail;
+                let new_part = new[head..new.len() - tail].to_vec();
+                floor = at + new_part.len();
+                drift = at as isize - (start_of(hunk) + head) as isize - growth;
+                growth += new_part.len() as isize - old_len as isize;
+                lines.splice(at..at + old_len, new_part);
+                results.push(HunkResult::Applied { offset: drift, fuzz: f });
+            }
+            None => results.push(HunkResult::Rejected),
+        }
+    }
+    (lines.concat(), results)
+}
+
+// The position nearest `expected` (and not before `floor`) where `old` appears
+fn find_lines(lines: &[Vec<u8>], old: &[Vec<u8>], expected: isize, floor: usize) -> Option<usize> {
+    let last = lines.len().checked_sub(old.len())?;
+    let expected = expected.clamp(floor as isize, last.max(floor) as isize) as usize;
+    let fits = |at: usize| at >= floor && at <= last && lines[at..at + old.len()] == *old;
+    (0..=lines.len()).find_map(|distance| {
+        [expected.checked_add(distance), expected.checked_sub(distance)].into_iter().flatten().find(|&at| fits(at))
+    })
+}
+
+// The rejected hunks of a file, as a patch of their own
+fn rejects(file: &FilePatch, results: &[HunkResult]) -> String {
+    let mut out = format!("--- a/{}\n+++ b/{}\n", file.path(), file.path());
+    for (hunk, result) in file.hunks.iter().zip(results) {
+        if *result == HunkResult::Rejected {
+            out.push_str(&hunk.header());
+            out.push('\n');
+            for line in &hunk.lines {
+                out.push_str(&line.to_line());
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+/// Formats a commit as a mail with its author, message and unified diff against
+/// its parent. `number` and `total` go in the "[PATCH n/m]" subject prefix.
+pub fn format_mail(hash: &str, commit: &Commit, diff_text: &str, number: usize, total: usize) -> String {
+    let mut out = format!("From {}{}\n", hash, MAIL_MARKER);
+    let author = commit.author.as_ref();
+    if let Some(author) = author {
+        out.push_str(&format!("From: {} <{}>\n", author.name, author.email));
+    }
+    let (timestamp, tz_offset) = author.map_or((commit.timestamp, 0), |author| (author.timestamp, author.tz_offset));
+    out.push_str(&format!("Date: {}\n", format_rfc2822_date(timestamp, tz_offset)));
+
+    let (subject, body) = commit.message.split_once('\n').unwrap_or((&commit.message, ""));
+    let prefix = if total > 1 { format!("[PATCH {}/{}]", number, total) } else { "[PATCH]".to_string() };
+    out.push_str(&format!("Subject: {} {}\n\n", prefix, subject.trim()));
+    let body = body.trim();
+    if !body.is_empty() {
+        out.push_str(body);
+        out.push_str("\n\n");
+    }
+    out.push_str("---\n");
+    out.push_str(diff_text);
+    out.push_str("-- \nsnap\n\n");
+    out
+}
+
+/// "0001-fix-the-parser.patch" for a commit's subject.
+pub fn patch_file_name(number: usize, message: &str) -> String {
+    let subject = message.lines().next().unwrap_or_default();
+    let mut slug = String::new();
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.trim_matches(['-', '.']).chars().take(52).collect();
+    format!("{:04}-{}.patch", number, slug.trim_end_matches('-'))
+}
+
+impl Repository {
+    /// A commit as a `format-patch` mail: its diff against its first parent (or
+    /// against nothing for a root commit), with renames found as `options` says.
+    pub fn format_patch(&self, hash: &str, number: usize, total: usize, options: &DiffOptions) -> Result<String> {
+        let commit = self.read_commit(hash)?;
+        let old = match commit.first_parent() {
+            None => Snapshot::Empty,
+            Some(parent) => Snapshot::Tree(self.read_commit(parent)?.tree_hash),
+        };
+        let new = Snapshot::Tree(commit.tree_hash.clone());
+
+        let mut diff_text = String::new();
+        for change in self.diff(&old, &new, &[], options)? {
+            if change.old_blob().is_some() || change.new_blob().is_some() {
+                let file_diff = self.diff_file(&change, &old, &new, options)?;
+                diff_text.push_str(&diff::format_unified(&change, &file_diff));
+            }
+        }
+        Ok(format_mail(hash, &commit, &diff_text, number, total))
+    }
+
+    /// The commits `format-patch` sends for `range`, as (file name, mail), oldest
+    /// first and without merges. `-<n>` is the last n commits and `<since>` means
+    /// `<since>..HEAD`.
+    pub fn format_patches(&self, range: &str, options: &DiffOptions) -> Result<Vec<(String, String)>> {
+        let mut commits: Vec<_> = if let Some(count) = range.strip_prefix('-') {
+            let count: usize = count.parse().map_err(|_| SnapError::InvalidArgument(format!("invalid commit count '{}'", count)))?;
+            self.log()?.into_iter().filter(|(_, commit)| !commit.is_merge()).take(count).collect()
+        } else {
+            let (exclude, include) = range.split_once("..").unwrap_or((range, "HEAD"));
+            let include = if include.is_empty() { "HEAD" } else { include };
+            let commits = self.commits_between(&self.resolve_revision(exclude)?, &self.resolve_revision(include)?)?;
+            commits.into_iter().filter(|(_, commit)| !commit.is_merge()).collect()
+        };
+        commits.reverse();
+
+        let total = commits.len();
+        let mut mails = Vec::new();
+        for (i, (hash, commit)) in commits.iter().enumerate() {
+            mails.push((patch_file_name(i + 1, &commit.message), self.format_patch(hash, i + 1, total, options)?));
+        }
+        Ok(mails)
+    }
+
+    /// Applies a mail from `format-patch` to the index and working tree and commits
+    /// it as its author. Nothing is applied when any hunk fails, and the commit is
+    /// None then or when the patch changes nothing.
+    pub fn apply_mail(&self, mail: &MailPatch, fuzz: usize) -> Result<(Vec<FileResult>, Option<String>)> {
+        // The commit must contain exactly what the patch changes
+        let status = self.status(".")?;
+        if !status.staged.is_empty() || !status.unstaged.is_empty() || !status.unmerged.is_empty() {
+            return Err(SnapError::Conflict("you have local changes; commit them before running am".to_string()));
+        }
+
+        let options = ApplyOptions { target: ApplyTarget::Both, fuzz, ..ApplyOptions::default() };
+        let results = self.apply(&mail.files, &options)?;
+        if !results.iter().all(FileResult::is_clean) {
+            return Ok((results, None));
+        }
+        let committed = match &mail.author {
+            Some(author) => self.commit_as(&mail.message, author.clone())?,
+            None => self.commit(&mail.message, None)?,
+        };
+        Ok((results, committed))
+    }
+
+    /// Applies a patch to the working tree and/or index. Unless `options.reject`
+    /// is set nothing is written when any file or hunk fails; the results say
+    /// which.
+    pub fn apply(&self, files: &[FilePatch], options: &ApplyOptions) -> Result<Vec<FileResult>> {
+        let uses_index = options.target != ApplyTarget::WorkTree;
+        let mut index = if uses_index { self.index()? } else { Index::new() };
+        let read = |path: &str, index: &Index| -> Option<Vec<u8>> {
+            match options.target {
+                ApplyTarget::Index => index.get(path).and_then(|blob| self.objects().read_blob(blob).ok()),
+                _ => fs::read(self.work_path(path)).ok(),
+            }
+        };
+
+        let mut results = Vec::new();
+        // Paths to delete, then (path, content, rejected hunks) to write
+        let mut removals = Vec::new();
+        let mut writes = Vec::new();
+        for file in files {
+            let path = file.path().to_string();
+            let mut result = FileResult { path: path.clone(), hunks: Vec::new(), error: None };
+            let old_content = match &file.old_path {
+                Some(old_path) => read(old_path, &index),
+                None => Some(Vec::new()),
+            };
+
+            if file.binary {
+                result.error = Some("binary patches can't be applied".to_string());
+            } else if let Some(old_path) = &file.old_path
+                && old_content.is_none()
+            {
+                result.error = Some(format!("{}: does not exist", old_path));
+            } else if let Some(new_path) = &file.new_path
+                && file.old_path.as_ref() != Some(new_path)
+                && read(new_path, &index).is_some()
+            {
+                result.error = Some(format!("{}: already exists", new_path));
+            } else {
+                let (content, hunks) = apply_hunks(&old_content.unwrap_or_default(), &file.hunks, options.fuzz);
+                result.hunks = hunks;
+                if file.new_path.is_none() && !content.is_empty() && result.is_clean() {
+                    result.error = Some(format!("{}: deleted file still has content", path));
+                }
+                if result.error.is_none() {
+                    if let Some(old_path) = &file.old_path
+                        && (file.new_path.as_ref() != Some(old_path) && !file.copy)
+                    {
+                        removals.push(old_path.clone());
+                    }
+                    if let Some(new_path) = &file.new_path {
+                        let rejected = (!result.is_clean()).then(|| rejects(file, &result.hunks));
+                        writes.push((new_path.clone(), content, rejected));
+                    }
+                }
+            }
+            results.push(result);
+        }
+
+        let clean = results.iter().all(FileResult::is_clean);
+        if options.check || (!clean && !options.reject) {
+            return Ok(results);
+        }
+
+        // Deletions go first so a file can replace a directory and the other way round
+        for path in removals {
+            if options.target != ApplyTarget::Index {
+                let full_path = self.work_path(&path);
+                fs::remove_file(&full_path).at(&full_path)?;
+            }
+            index.remove(&path);
+        }
+        for (path, content, rejected) in writes {
+            if options.target != ApplyTarget::Index {
+                let full_path = self.work_path(&path);
+                if let Some(parent) = full_path.parent() {
+                    fs::create_dir_all(parent).at(parent)?;
+                }
+                fs::write(&full_path, &content).at(&full_path)?;
+                if let Some(rejected) = rejected {
+                    let rej_path = self.work_path(&format!("{}.rej", path));
+                    fs::write(&rej_path, rejected).at(&rej_path)?;
+                }
+            }
+            if uses_index {
+                index.insert(path, self.objects().save_blob(&content)?);
+            }
+        }
+        if uses_index {
+            self.write_index(&index)?;
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestRepo;
+
+    #[test]
+    fn format_patches_then_apply_mail_replays_the_commits() {
+        let repo = TestRepo::new("patch-am");
+        repo.write("f", "one\ntwo\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.write("f", "one\nTWO\n");
+        let first = repo.commit_all("change f");
+        repo.write("g", "g\n");
+        repo.commit_all("add g\n\nWith a body.");
+
+        let mails = repo.format_patches(&format!("{}..topic", main), &DiffOptions::default()).unwrap();
+        assert_eq!(mails.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["0001-change-f.patch", "0002-add-g.patch"]);
+        assert_eq!(repo.format_patches("-1", &DiffOptions::default()).unwrap()[0].0, "0001-add-g.patch");
+        assert!(matches!(repo.format_patches("-x", &DiffOptions::default()), Err(SnapError::InvalidArgument(_))));
+
+        repo.switch(&main);
+        for (_, mail) in &mails {
+            let (results, committed) = repo.apply_mail(&parse_mail(mail).unwrap(), 0).unwrap();
+            assert!(results.iter().all(FileResult::is_clean));
+            assert!(committed.is_some());
+        }
+        let log = repo.log().unwrap();
+        assert_eq!(log[0].1.message, "add g\n\nWith a body.");
+        assert_eq!(log[1].1.author, repo.read_commit(&first).unwrap().author);
+        assert_eq!(repo.read("f").as_deref(), Some("one\nTWO\n"));
+        assert_eq!(repo.read("g").as_deref(), Some("g\n"));
+
+        // A patch that no longer applies leaves everything as it was
+        let (results, committed) = repo.apply_mail(&parse_mail(&mails[0].1).unwrap(), 0).unwrap();
+        assert!(!results.iter().all(FileResult::is_clean));
+        assert_eq!(committed, None);
+        assert_eq!(repo.log().unwrap().len(), 3);
+
+        repo.write("f", "local\n");
+        assert!(matches!(repo.apply_mail(&parse_mail(&mails[1].1).unwrap(), 0), Err(SnapError::Conflict(_))));
+    }
+
+    // Ten numbered lines, "1\n" to "10\n"
+    fn numbered() -> String {
+        (1..=10).map(|n| format!("{}\n", n)).collect()
+    }
+
+    const CHANGE_FIVE: &str = "--- a/f\n+++ b/f\n@@ -3,5 +3,5 @@\n 3\n 4\n-5\n+five\n 6\n 7\n";
+
+    #[test]
+    fn mails_carry_an_rfc2822_date_that_reads_back() {
+        let author = Signature { name: "Jane Doe".to_string(), email: "jane@example.com".to_string(), timestamp: 1_729_152_000, tz_offset: 120 };
+        let commit = Commit { tree_hash: String::new(), parents: Vec::new(), timestamp: 0, message: "Fix it\n\nProperly.".to_string(), author: Some(author.clone()), committer: None };
+        let mail = format_mail("0123", &commit, CHANGE_FIVE, 1, 1);
+        assert!(mail.contains("\nDate: Thu, 17 Oct 2024 10:00:00 +0200\n"), "{}", mail);
+
+        let parsed = parse_mail(&mail).unwrap();
+        assert_eq!(parsed.author, Some(author.clone()));
+        assert_eq!(parsed.message, "Fix it\n\nProperly.");
+        assert_eq!(parsed.files.len(), 1);
+
+        // Mails written before the header followed RFC 2822 still read
+        let old = mail.replace("Thu, 17 Oct 2024 10:00:00 +0200", "2024-10-17 10:00:00 +0200");
+        assert_eq!(parse_mail(&old).unwrap().author, Some(author));
+        let bad = mail.replace("10:00:00 +0200", "99:99:99 +9999");
+        assert!(matches!(parse_mail(&bad), Err(SnapError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn hunks_apply_at_an_offset_and_with_fuzz() {
+        let hunks = &parse_diff(CHANGE_FIVE).unwrap()[0].hunks;
+        let (content, results) = apply_hunks(numbered().as_bytes(), hunks, 0);
+        assert_eq!(results, [HunkResult::Applied { offset: 0, fuzz: 0 }]);
+        assert_eq!(content, numbered().replace("5\n", "five\n").into_bytes());
+
+        // Three lines added above the hunk move it down
+        let shifted = format!("a\nb\nc\n{}", numbered());
+        let (content, results) = apply_hunks(shifted.as_bytes(), hunks, 0);
+        assert_eq!(results, [HunkResult::Applied { offset: 3, fuzz: 0 }]);
+        assert_eq!(content, shifted.replace("\n5\n", "\nfive\n").into_bytes());
+
+        // An outer context line that changed needs fuzz
+        let edited = numbered().replace("7\n", "seven\n");
+        assert_eq!(apply_hunks(edited.as_bytes(), hunks, 0).1, [HunkResult::Rejected]);
+        let (content, results) = apply_hunks(edited.as_bytes(), hunks, 1);
+        assert_eq!(results, [HunkResult::Applied { offset: 0, fuzz: 1 }]);
+        assert_eq!(content, edited.replace("5\n", "five\n").into_bytes());
+
+        // Fuzz never drops the lines being changed
+        let gone = numbered().replace("5\n", "");
+        assert_eq!(apply_hunks(gone.as_bytes(), hunks, 2).1, [HunkResult::Rejected]);
+    }
+
+    #[test]
+    fn reject_applies_what_fits_and_writes_the_rest_to_a_rej_file() {
+        let repo = TestRepo::new("patch-reject");
+        repo.write("f", &numbered().replace("9\n", "nine\n"));
+        let patch = format!("{}@@ -8,3 +8,3 @@\n 8\n-9\n+NINE\n 10\n", CHANGE_FIVE);
+        let files = parse_diff(&patch).unwrap();
+
+        // Without --reject a failing hunk stops the whole file
+        let results = repo.apply(&files, &ApplyOptions { fuzz: 0, ..ApplyOptions::default() }).unwrap();
+        assert_eq!(results[0].hunks, [HunkResult::Applied { offset: 0, fuzz: 0 }, HunkResult::Rejected]);
+        assert_eq!(repo.read("f"), Some(numbered().replace("9\n", "nine\n")));
+        assert_eq!(repo.read("f.rej"), None);
+
+        repo.apply(&files, &ApplyOptions { fuzz: 0, reject: true, ..ApplyOptions::default() }).unwrap();
+        assert_eq!(repo.read("f"), Some(numbered().replace("5\n", "five\n").replace("9\n", "nine\n")));
+        assert_eq!(repo.read("f.rej").as_deref(), Some("--- a/f\n+++ b/f\n@@ -8,3 +8,3 @@\n 8\n-9\n+NINE\n 10\n"));
+    }
+
+    #[test]
+    fn paths_outside_the_worktree_are_refused() {
+        for path in ["", "/etc/passwd", "../up", "a/../../up", ".snap/HEAD", "src/.snap/config"] {
+            assert!(matches!(checked_path(path), Err(SnapError::InvalidArgument(_))), "{:?}", path);
+        }
+        assert_eq!(checked_path("src/a..b/.snapshot").unwrap(), "src/a..b/.snapshot");
+
+        for (old, new) in [("a/../x", "b/../x"), ("/dev/null", "b/.snap/HEAD"), ("a//etc/passwd", "b//etc/passwd")] {
+            let patch = format!("--- {}\n+++ {}\n@@ -0,0 +1 @@\n+x\n", old, new);
+            assert!(matches!(parse_diff(&patch), Err(SnapError::InvalidArgument(_))), "{:?}", new);
+        }
+        let patch = "diff --git a/ok b/../escape\nrename from ok\nrename to ../escape\n";
+        assert!(parse_diff(patch).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::error::{IoContext, Result, SnapError};
+use crate::index::Index;
+use crate::merge::{MergeConflict, MergeLabels, MergeOptions};
+use crate::object::Commit;
+use crate::repository::Repository;
+
+/// What a rebase did, or where it stopped.
+#[derive(Debug, Default)]
+pub struct Rebase {
+    pub onto: String,
+    // The branch being rebased, None when HEAD was detached
+    pub branch: Option<String>,
+    // Each replayed commit with its copy, or None if it changed nothing any more and was dropped
+    pub applied: Vec<(String, Option<String>)>,
+    // The commit that could not be applied and its conflicts; the rebase waits for --continue
+    pub stopped: Option<(String, Vec<MergeConflict>)>,
+    // Nothing to replay: the branch already contains upstream
+    pub up_to_date: bool,
+}
+
+// A rebase in progress lives in `.snap/rebase-merge/`, one file per piece of state:
+//   head-name        "refs/heads/<branch>", or "detached HEAD"
+//   orig-head        where HEAD was before the rebase
+//   onto             the commit the branch is replayed onto
+//   git-rebase-todo  "pick <hash> <subject>" for every commit still to replay, oldest first
+//   done             the commits replayed so far, in the same format
+//   stopped-sha      the commit whose conflicts are being resolved
+// Every step updates the files before moving on, so a rebase interrupted at
+// any point can still be continued or aborted.
+impl Repository {
+    fn rebase_path(&self, name: &str) -> PathBuf {
+        self.snap_path("rebase-merge").join(name)
+    }
+
+    pub fn rebase_in_progress(&self) -> bool {
+        self.snap_path("rebase-merge").is_dir()
+    }
+
+    fn read_rebase_file(&self, name: &str) -> Result<String> {
+        let path = self.rebase_path(name);
+        Ok(fs::read_to_string(&path).at(&path)?.trim_end().to_string())
+    }
+
+    fn write_rebase_file(&self, name: &str, content: &str) -> Result<()> {
+        let path = self.rebase_path(name);
+        fs::write(&path, content).at(&path)
+    }
+
+    // The hashes of the commits still to replay
+    fn rebase_todo(&self) -> Result<Vec<String>> {
+        let todo = self.read_rebase_file("git-rebase-todo")?;
+        Ok(todo.lines().filter_map(|line| line.strip_prefix("pick ")?.split_whitespace().next()).map(str::to_string).collect())
+    }
+
+    fn pick_line(&self, hash: &str) -> Result<String> {
+        Ok(format!("pick {} {}\n", hash, self.read_commit(hash)?.subject()))
+    }
+
+    // Moves the first commit of the todo list to the done list
+    fn finish_pick(&self) -> Result<()> {
+        let mut todo = self.rebase_todo()?;
+        if todo.is_empty() {
+            return Ok(());
+        }
+        let hash = todo.remove(0);
+        let done_path = self.rebase_path("done");
+        let mut done = fs::read_to_string(&done_path).unwrap_or_default();
+        done.push_str(&self.pick_line(&hash)?);
+        fs::write(&done_path, done).at(&done_path)?;
+
+        let mut lines = String::new();
+        for hash in &todo {
+            lines.push_str(&self.pick_line(hash)?);
+        }
+        self.write_rebase_file("git-rebase-todo", &lines)?;
+        match fs::remove_file(self.rebase_path("stopped-sha")) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e).at(self.rebase_path("stopped-sha")),
+            _ => Ok(()),
+        }
+    }
+
+    fn rebase_progress(&self) -> Result<Rebase> {
+        let head_name = self.read_rebase_file("head-name")?;
+        let branch = head_name.strip_prefix("refs/heads/").map(str::to_string);
+        Ok(Rebase { onto: self.read_rebase_file("onto")?, branch, ..Rebase::default() })
+    }
+
+    // Commits the index with the author and message of the commit being replayed
+    fn commit_pick(&self, commit: &Commit) -> Result<Option<String>> {
+        match &commit.author {
+            Some(author) => self.commit_as(&commit.message, author.clone()),
+            None => self.commit(&commit.message, None),
+        }
+    }
+
+    /// Replays the commits on the current branch that `upstream` doesn't have, oldest
+    /// first, on top of `upstream`, and moves the branch to the result. Merge commits
+    /// are left out, and commits whose changes upstream already has are dropped.
+    /// On a conflict the rebase stops with the conflicts in the index, to be
+    /// resumed with `rebase_continue` or `rebase_skip`, or undone with `rebase_abort`.
+    pub fn rebase(&self, upstream: &str, options: &MergeOptions) -> Result<Rebase> {
+        if self.rebase_in_progress() {
+            return Err(SnapError::Conflict("a rebase is already in progress; use --continue, --skip or --abort".to_string()));
+        }
+        if self.merge_head()?.is_some() {
+            return Err(SnapError::Conflict("you have not concluded your merge (MERGE_HEAD exists); commit the result or abort it".to_string()));
+        }
+        let head = self.head()?;
+        if head.is_empty() {
+            return Err(SnapError::BadRevision("HEAD".to_string()));
+        }
+        let onto = self.resolve_revision(upstream)?;
+        let branch = self.current_branch()?;
+        if self.is_ancestor(&onto, &head)? {
+            return Ok(Rebase { onto, branch, up_to_date: true, ..Rebase::default() });
+        }
+        let status = self.status(".")?;
+        if !status.staged.is_empty() || !status.unstaged.is_empty() || !status.unmerged.is_empty() {
+            return Err(SnapError::Conflict("you have local changes; commit them before rebasing".to_string()));
+        }
+
+        let mut commits = self.commits_between(&onto, &head)?;
+        commits.retain(|(_, commit)| !commit.is_merge());
+        commits.reverse();
+
+        let state_dir = self.snap_path("rebase-merge");
+        fs::create_dir_all(&state_dir).at(&state_dir)?;
+        let head_name = match &branch {
+            Some(branch) => format!("refs/heads/{}", branch),
+            None => "detached HEAD".to_string(),
+        };
+        self.write_rebase_file("head-name", &head_name)?;
+        self.write_rebase_file("orig-head", &head)?;
+        self.write_rebase_file("onto", &onto)?;
+        let todo: String = commits.iter().map(|(hash, commit)| format!("pick {} {}\n", hash, commit.subject())).collect();
+        self.write_rebase_file("git-rebase-todo", &todo)?;
+
+        // Start from upstream with HEAD detached; the branch only moves once every commit is replayed
+        let moved = self.update_work_tree(&self.commit_files(&head)?, &self.commit_files(&onto)?, &Default::default());
+        if let Err(e) = moved {
+            fs::remove_dir_all(&state_dir).at(&state_dir)?;
+            return Err(e);
+        }
+        self.reset_index(&self.read_commit(&onto)?.tree_hash)?;
+        self.detach_head(&onto)?;
+        self.replay(options)
+    }
+
+    /// Resumes a rebase stopped on conflicts: commits the resolved index as the
+    /// stopped commit (dropping it if nothing is left to commit) and goes on.
+    pub fn rebase_continue(&self, options: &MergeOptions) -> Result<Rebase> {
+        if !self.rebase_in_progress() {
+            return Err(SnapError::Conflict("no rebase in progress".to_string()));
+        }
+        let mut rebase = self.rebase_progress()?;
+        if let Ok(hash) = self.read_rebase_file("stopped-sha") {
+            let commit = self.read_commit(&hash)?;
+            let new = self.commit_pick(&commit)?;
+            self.finish_pick()?;
+            rebase.applied.push((hash, new));
+        }
+        let mut rest = self.replay(options)?;
+        rebase.applied.append(&mut rest.applied);
+        rest.applied = rebase.applied;
+        Ok(rest)
+    }
+
+    /// Resumes a rebase stopped on conflicts without the stopped commit.
+    pub fn rebase_skip(&self, options: &MergeOptions) -> Result<Rebase> {
+        if !self.rebase_in_progress() {
+            return Err(SnapError::Conflict("no rebase in progress".to_string()));
+        }
+        // Without a stopped commit the next one in the todo list was never tried
+        if !self.rebase_path("stopped-sha").is_file() {
+            return Err(SnapError::Conflict("no commit to skip: the rebase didn't stop on one; use --continue or --abort".to_string()));
+        }
+        self.reset_hard(&self.head()?)?;
+        self.finish_pick()?;
+        self.replay(options)
+    }
+
+    /// Gives up a rebase, putting HEAD, the index and the worktree back as they
+    /// were before it started. Returns the commit HEAD is back at.
+    pub fn rebase_abort(&self) -> Result<String> {
+        if !self.rebase_in_progress() {
+            return Err(SnapError::Conflict("no rebase in progress".to_string()));
+        }
+        let orig_head = self.read_rebase_file("orig-head")?;
+        let head_name = self.read_rebase_file("head-name")?;
+        self.reset_hard(&orig_head)?;
+        match head_name.strip_prefix("refs/heads/") {
+            Some(branch) => self.set_head_branch(branch)?,
+            None => self.detach_head(&orig_head)?,
+        }
+        let state_dir = self.snap_path("rebase-merge");
+        fs::remove_dir_all(&state_dir).at(&state_dir)?;
+        Ok(orig_head)
+    }
+
+    // Applies the commits left in the todo list one by one on top of HEAD
+    fn replay(&self, options: &MergeOptions) -> Result<Rebase> {
+        let mut rebase = self.rebase_progress()?;
+        while let Some(hash) = self.rebase_todo()?.into_iter().next() {
+            let commit = self.read_commit(&hash)?;
+            let parent_tree = match commit.first_parent() {
+                Some(parent) => self.read_commit(parent)?.tree_hash,
+                None => self.build_tree(&Index::new())?,
+            };
+            let head_tree = self.read_commit(&self.head()?)?.tree_hash;
+            let labels = MergeLabels {
+                base: format!("parent of {}", &hash[..12]),
+                ours: "HEAD".to_string(),
+                theirs: format!("{} ({})", &hash[..12], commit.subject()),
+            };
+
+            let result = self.merge_into_work_tree(&parent_tree, &head_tree, &commit.tree_hash, &labels, options)?;
+            if !result.conflicts.is_empty() {
+                self.write_rebase_file("stopped-sha", &hash)?;
+                rebase.stopped = Some((hash, result.conflicts));
+                return Ok(rebase);
+            }
+            let new = self.commit_pick(&commit)?;
+            self.finish_pick()?;
+            rebase.applied.push((hash, new));
+        }
+
+        // Every commit is replayed: the branch moves to the result
+        if let Some(branch) = &rebase.branch {
+            let ref_path = self.branch_ref_path(branch);
+            fs::write(&ref_path, self.head()?).at(&ref_path)?;
+            self.set_head_branch(branch)?;
+        }
+        let state_dir = self.snap_path("rebase-merge");
+        fs::remove_dir_all(&state_dir).at(&state_dir)?;
+        Ok(rebase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestRepo;
+
+    // topic changes `f` in two commits and adds `g`; main changes `f` too, in a
+    // way that conflicts with the first topic commit only
+    fn diverged(name: &str) -> (TestRepo, String) {
+        let repo = TestRepo::new(name);
+        repo.write("f", "one\ntwo\nthree\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.write("f", "one\ntwo\nTOPIC\n");
+        repo.commit_all("topic f");
+        repo.write("g", "g\n");
+        repo.commit_all("add g");
+        repo.switch(&main);
+        repo.write("f", "one\ntwo\nMAIN\n");
+        repo.commit_all("main f");
+        repo.switch("topic");
+        (repo, main)
+    }
+
+    fn subjects(repo: &Repository) -> Vec<String> {
+        repo.log().unwrap().iter().map(|(_, commit)| commit.subject().to_string()).collect()
+    }
+
+    #[test]
+    fn clean_rebase_replays_onto_upstream() {
+        let repo = TestRepo::new("rebase-clean");
+        repo.write("a", "a\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.write("b", "b\n");
+        repo.commit_all("add b");
+        repo.write("same", "same\n");
+        repo.commit_all("same change");
+        repo.switch(&main);
+        repo.write("c", "c\n");
+        repo.write("same", "same\n");
+        let upstream = repo.commit_all("add c");
+        repo.switch("topic");
+
+        let rebase = repo.rebase(&main, &MergeOptions::default()).unwrap();
+        assert!(rebase.stopped.is_none());
+        assert_eq!(rebase.onto, upstream);
+        // The change upstream already has is dropped
+        assert_eq!(rebase.applied.iter().map(|(_, new)| new.is_some()).collect::<Vec<_>>(), vec![true, false]);
+        assert_eq!(subjects(&repo), vec!["add b", "add c", "base"]);
+        assert_eq!(repo.current_branch(), "topic");
+        assert_eq!(repo.read("c").as_deref(), Some("c\n"));
+        assert!(!repo.rebase_in_progress());
+
+        assert!(repo.rebase(&main, &MergeOptions::default()).unwrap().up_to_date);
+    }
+
+    #[test]
+    fn conflict_stops_until_continue() {
+        let (repo, main) = diverged("rebase-continue");
+        let rebase = repo.rebase(&main, &MergeOptions::default()).unwrap();
+        let (stopped, conflicts) = rebase.stopped.unwrap();
+        assert_eq!(repo.read_commit(&stopped).unwrap().subject(), "topic f");
+        assert_eq!(conflicts.len(), 1);
+        assert!(repo.rebase_in_progress());
+        assert_eq!(Repository::current_branch(&repo).unwrap(), None);
+
+        // Unresolved conflicts can't be committed
+        assert!(repo.rebase_continue(&MergeOptions::default()).is_err());
+
+        // The state is on disk, so a fresh handle carries on from it
+        repo.write("f", "one\ntwo\nMAIN+TOPIC\n");
+        let reopened = Repository::discover(repo.work_tree()).unwrap();
+        reopened.add("f").unwrap();
+        let rebase = reopened.rebase_continue(&MergeOptions::default()).unwrap();
+        assert!(rebase.stopped.is_none());
+        assert_eq!(rebase.applied.len(), 2);
+        assert_eq!(subjects(&repo), vec!["add g", "topic f", "main f", "base"]);
+        assert_eq!(repo.read("f").as_deref(), Some("one\ntwo\nMAIN+TOPIC\n"));
+        assert!(!repo.rebase_in_progress());
+    }
+
+    #[test]
+    fn skip_drops_the_stopped_commit() {
+        let (repo, main) = diverged("rebase-skip");
+        repo.rebase(&main, &MergeOptions::default()).unwrap();
+        let rebase = repo.rebase_skip(&MergeOptions::default()).unwrap();
+        assert!(rebase.stopped.is_none());
+        assert_eq!(subjects(&repo), vec!["add g", "main f", "base"]);
+        assert_eq!(repo.read("f").as_deref(), Some("one\ntwo\nMAIN\n"));
+        assert!(repo.rebase_skip(&MergeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn skip_needs_a_stopped_commit() {
+        let (repo, main) = diverged("rebase-skip-unstopped");
+        repo.rebase(&main, &MergeOptions::default()).unwrap();
+        fs::remove_file(repo.rebase_path("stopped-sha")).unwrap();
+        let todo = repo.rebase_todo().unwrap();
+        assert!(matches!(repo.rebase_skip(&MergeOptions::default()), Err(SnapError::Conflict(_))));
+        assert_eq!(repo.rebase_todo().unwrap(), todo);
+    }
+
+    #[test]
+    fn abort_puts_everything_back() {
+        let (repo, main) = diverged("rebase-abort");
+        let orig_head = repo.head().unwrap();
+        repo.rebase(&main, &MergeOptions::default()).unwrap();
+        assert_eq!(repo.rebase_abort().unwrap(), orig_head);
+        assert_eq!(repo.head().unwrap(), orig_head);
+        assert_eq!(repo.current_branch(), "topic");
+        assert_eq!(repo.read("f").as_deref(), Some("one\ntwo\nTOPIC\n"));
+        assert!(repo.conflicts().unwrap().is_empty());
+        assert!(!repo.rebase_in_progress());
+        assert!(repo.rebase_abort().is_err());
+    }
+
+    #[test]
+    fn rebase_onto_a_directory_turned_file() {
+        let repo = TestRepo::new("rebase-dir-to-file");
+        repo.write("d/f", "f\n");
+        repo.commit_all("base");
+        let main = repo.current_branch();
+        repo.branch("topic");
+        repo.write("t", "t\n");
+        repo.commit_all("add t");
+        repo.switch(&main);
+        repo.remove("d");
+        repo.write("d", "now a file\n");
+        repo.commit_all("d is a file");
+        repo.switch("topic");
+
+        let rebase = repo.rebase(&main, &MergeOptions::default()).unwrap();
+        assert!(rebase.stopped.is_none());
+        assert_eq!(repo.read("d").as_deref(), Some("now a file\n"));
+        assert_eq!(repo.read("t").as_deref(), Some("t\n"));
+
+        // And back again when aborting a rebase stopped on top of it
+        repo.write("d2/f", "x\n");
+        repo.commit_all("add d2");
+        let orig_head = repo.head().unwrap();
+        repo.switch(&main);
+        repo.remove("d");
+        repo.write("d/f", "f again\n");
+        repo.write("t", "main t\n");
+        repo.commit_all("d is a directory again");
+        repo.switch("topic");
+        assert!(repo.rebase(&main, &MergeOptions::default()).unwrap().stopped.is_some());
+        assert_eq!(repo.rebase_abort().unwrap(), orig_head);
+        assert_eq!(repo.read("d").as_deref(), Some("now a file\n"));
+    }
+}
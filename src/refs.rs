@@ -0,0 +1,154 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::error::{IoContext, Result, SnapError};
+use crate::object::ObjectKind;
+use crate::repository::Repository;
+
+impl Repository {
+    /// The commit HEAD points at, or an empty string before the first commit.
+    pub fn head(&self) -> Result<String> {
+        let head_path = self.snap_path("HEAD");
+        let head = fs::read_to_string(&head_path).at(&head_path)?;
+        let head = head.trim();
+
+        // Check if HEAD is a symbolic ref (pointing to a branch)
+        if let Some(branch_path) = head.strip_prefix("ref: ") {
+            let ref_path = self.snap_path(branch_path);
+            return match fs::read_to_string(&ref_path) {
+                Ok(commit_hash) => Ok(commit_hash.trim().to_string()),
+                // The branch is unborn until its first commit
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+                Err(e) => Err(e).at(&ref_path),
+            };
+        }
+
+        Ok(head.to_string())
+    }
+
+    /// The branch HEAD points at, or None when HEAD is detached.
+    pub fn current_branch(&self) -> Result<Option<String>> {
+        let head_path = self.snap_path("HEAD");
+        let head_content = fs::read_to_string(&head_path).at(&head_path)?;
+        Ok(head_content.trim().strip_prefix("ref: refs/heads/").map(str::to_string))
+    }
+
+    pub fn branches(&self) -> Result<Vec<String>> {
+        let heads_dir = self.snap_path("refs/heads");
+        let mut branches: Vec<String> = match fs::read_dir(&heads_dir) {
+            Ok(entries) => entries.flatten().map(|entry| entry.file_name().to_string_lossy().to_string()).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).at(&heads_dir),
+        };
+        branches.sort();
+        Ok(branches)
+    }
+
+    pub(crate) fn branch_ref_path(&self, branch_name: &str) -> PathBuf {
+        self.snap_path(&format!("refs/heads/{}", branch_name))
+    }
+
+    // None if the branch doesn't exist
+    pub fn branch_commit(&self, branch_name: &str) -> Result<Option<String>> {
+        match fs::read_to_string(self.branch_ref_path(branch_name)) {
+            Ok(hash) => Ok(Some(hash.trim().to_string())),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Creates a branch at HEAD and returns the commit it points to.
+    pub fn create_branch(&self, branch_name: &str) -> Result<String> {
+        let latest_commit = self.head()?;
+        if latest_commit.is_empty() {
+            return Err(SnapError::BadRevision("HEAD".to_string()));
+        }
+        let ref_path = self.branch_ref_path(branch_name);
+        if fs::metadata(&ref_path).is_ok() {
+            return Err(SnapError::Conflict(format!("branch '{}' already exists", branch_name)));
+        }
+        fs::write(&ref_path, &latest_commit).at(&ref_path)?;
+        Ok(latest_commit)
+    }
+
+    // Moves the current branch to `commit_hash`, or HEAD itself when detached
+    pub(crate) fn update_head(&self, commit_hash: &str) -> Result<()> {
+        let head_path = self.snap_path("HEAD");
+        let head_content = fs::read_to_string(&head_path).at(&head_path)?;
+
+        if let Some(branch_path) = head_content.trim().strip_prefix("ref: ") {
+            let ref_path = self.snap_path(branch_path);
+            if let Some(parent) = ref_path.parent() {
+                fs::create_dir_all(parent).at(parent)?;
+            }
+            fs::write(&ref_path, commit_hash).at(&ref_path)
+        } else {
+            fs::write(&head_path, commit_hash).at(&head_path)
+        }
+    }
+
+    pub(crate) fn detach_head(&self, commit_hash: &str) -> Result<()> {
+        let head_path = self.snap_path("HEAD");
+        fs::write(&head_path, commit_hash).at(&head_path)
+    }
+
+    pub(crate) fn set_head_branch(&self, branch_name: &str) -> Result<()> {
+        let head_path = self.snap_path("HEAD");
+        fs::write(&head_path, format!("ref: refs/heads/{}", branch_name)).at(&head_path)
+    }
+
+    /// Resolves HEAD, a branch name, or a full or unambiguous abbreviated commit hash,
+    /// optionally followed by `^<n>` for the n-th parent (the first if n is left
+    /// out, the commit itself for `^0`) or `~<n>` to walk back n first parents.
+    pub fn resolve_revision(&self, rev: &str) -> Result<String> {
+        let Some(split) = rev.find(['^', '~']) else {
+            return self.resolve_name(rev);
+        };
+        let mut hash = self.resolve_name(&rev[..split])?;
+
+        let mut suffix = &rev[split..];
+        while !suffix.is_empty() {
+            let digits = suffix[1..].find(['^', '~']).map_or(suffix.len(), |i| i + 1);
+            let count: usize = match &suffix[1..digits] {
+                "" => 1,
+                n => n.parse().map_err(|_| SnapError::BadRevision(rev.to_string()))?,
+            };
+            let parent = |hash: &str, n: usize| -> Result<String> {
+                let parents = self.read_commit(hash)?.parents;
+                parents.into_iter().nth(n - 1).ok_or_else(|| SnapError::BadRevision(rev.to_string()))
+            };
+            if suffix.starts_with('^') {
+                if count > 0 {
+                    hash = parent(&hash, count)?;
+                }
+            } else {
+                for _ in 0..count {
+                    hash = parent(&hash, 1)?;
+                }
+            }
+            suffix = &suffix[digits..];
+        }
+        Ok(hash)
+    }
+
+    fn resolve_name(&self, rev: &str) -> Result<String> {
+        let hash = if rev == "HEAD" {
+            self.head()?
+        } else if let Some(hash) = self.branch_commit(rev)? {
+            hash
+        } else if rev.len() >= 4 && rev.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let rev = rev.to_lowercase();
+            let mut matches = self.objects().list().into_iter().filter(|hash| hash.starts_with(&rev));
+            match (matches.next(), matches.next()) {
+                (Some(hash), None) => hash,
+                (Some(_), Some(_)) => return Err(SnapError::InvalidArgument(format!("short hash '{}' is ambiguous", rev))),
+                _ => return Err(SnapError::BadRevision(rev)),
+            }
+        } else {
+            return Err(SnapError::BadRevision(rev.to_string()));
+        };
+
+        if hash.is_empty() || self.objects().read_kind(&hash).ok() != Some(ObjectKind::Commit) {
+            return Err(SnapError::BadRevision(rev.to_string()));
+        }
+        Ok(hash)
+    }
+}
@@ -0,0 +1,657 @@
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::Config;
+use crate::diff::{self, DiffOptions, FileDiff, Snapshot, TreeChange, TreeOverlay};
+use crate::error::{IoContext, Result, SnapError};
+use crate::index::{self, in_directory, ConflictEntry, Conflicts, Index, Stage};
+use crate::object::{hash_object, Commit, ObjectKind, ObjectStore, Tree, TreeEntry};
+use crate::signature::{parse_identity, Signature};
+
+/// An open repository: the `.snap` directory and the worktree it tracks.
+pub struct Repository {
+    snap_dir: PathBuf,
+    work_tree: PathBuf,
+    objects: ObjectStore,
+    migrated_objects: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileStatus {
+    New,
+    Modified,
+    Deleted,
+}
+
+impl FileStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            FileStatus::New => "new file",
+            FileStatus::Modified => "modified",
+            FileStatus::Deleted => "deleted",
+        }
+    }
+}
+
+/// The result of `status`, with every list sorted by path.
+#[derive(Debug)]
+pub struct Status {
+    // None when HEAD is detached
+    pub branch: Option<String>,
+    pub head: String,
+    // Index compared with HEAD
+    pub staged: Vec<(FileStatus, String)>,
+    // Working tree compared with the index
+    pub unstaged: Vec<(FileStatus, String)>,
+    pub untracked: Vec<String>,
+    // Paths a merge left conflicted, with the versions it found
+    pub unmerged: Vec<(String, ConflictEntry)>,
+    // Whether a merge is waiting to be committed
+    pub merging: bool,
+    // Whether a rebase is waiting for --continue, --skip or --abort
+    pub rebasing: bool,
+}
+
+impl Status {
+    pub fn is_clean(&self) -> bool {
+        self.staged.is_empty() && self.unstaged.is_empty() && self.untracked.is_empty() && self.unmerged.is_empty()
+    }
+}
+
+/// What a checkout did to the working tree.
+#[derive(Debug, Default)]
+pub struct Checkout {
+    pub commit: String,
+    pub restored: Vec<String>,
+    pub deleted: Vec<String>,
+    // Files that could not be deleted, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+// Seconds since the Unix epoch
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+// Newest first, but a commit only once every commit in the set that has it as a
+// parent has been listed, so histories read top-down even when timestamps tie
+fn order_commits(mut commits: HashMap<String, Commit>) -> Vec<(String, Commit)> {
+    let mut children: HashMap<String, usize> = HashMap::new();
+    for commit in commits.values() {
+        for parent in &commit.parents {
+            *children.entry(parent.clone()).or_default() += 1;
+        }
+    }
+
+    let mut ready: BinaryHeap<(i64, String)> =
+        commits.iter().filter(|(hash, _)| !children.contains_key(*hash)).map(|(hash, commit)| (commit.timestamp, hash.clone())).collect();
+    let mut ordered = Vec::with_capacity(commits.len());
+    while let Some((_, hash)) = ready.pop() {
+        let Some(commit) = commits.remove(&hash) else {
+            continue;
+        };
+        for parent in &commit.parents {
+            if let Some(count) = children.get_mut(parent) {
+                *count -= 1;
+                if *count == 0
+                    && let Some(parent_commit) = commits.get(parent)
+                {
+                    ready.push((parent_commit.timestamp, parent.clone()));
+                }
+            }
+        }
+        ordered.push((hash, commit));
+    }
+    ordered
+}
+
+// Resolves "." and ".." without touching the filesystem
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+impl Repository {
+    /// Creates (or reinitialises) a repository whose worktree is `work_tree`. The
+    /// repository directory defaults to `<work_tree>/.snap`.
+    pub fn init(work_tree: &Path, snap_dir: Option<&Path>) -> Result<Repository> {
+        let work_tree = std::path::absolute(work_tree).at(work_tree)?;
+        let snap_dir = normalize_path(&work_tree.join(snap_dir.unwrap_or(Path::new(".snap"))));
+        for dir in [snap_dir.join("objects"), snap_dir.join("refs/heads")] {
+            fs::create_dir_all(&dir).at(&dir)?;
+        }
+        let head_path = snap_dir.join("HEAD");
+        if fs::metadata(&head_path).is_err() {
+            let branch = Config::load(Some(&snap_dir)).get("init.defaultBranch").unwrap_or_else(|| "main".to_string());
+            fs::write(&head_path, format!("ref: refs/heads/{}", branch)).at(&head_path)?;
+        }
+        Repository::open(&work_tree, &snap_dir)
+    }
+
+    /// Opens the repository at `snap_dir`, moving objects from the old flat layout
+    /// into fan-out directories if needed.
+    pub fn open(work_tree: &Path, snap_dir: &Path) -> Result<Repository> {
+        let work_tree = normalize_path(&std::path::absolute(work_tree).at(work_tree)?);
+        let snap_dir = normalize_path(&std::path::absolute(snap_dir).at(snap_dir)?);
+        if !snap_dir.join("HEAD").is_file() {
+            return Err(SnapError::NotARepository);
+        }
+        let objects = ObjectStore::new(snap_dir.join("objects"));
+        let migrated_objects = objects.migrate_flat()?;
+        Ok(Repository { snap_dir, work_tree, objects, migrated_objects })
+    }
+
+    /// Finds the repository containing `start` by walking up to the first `.snap`.
+    pub fn discover(start: &Path) -> Result<Repository> {
+        let start = std::path::absolute(start).at(start)?;
+        match start.ancestors().find(|dir| dir.join(".snap/HEAD").is_file()) {
+            Some(root) => Repository::open(root, &root.join(".snap")),
+            None => Err(SnapError::NotARepository),
+        }
+    }
+
+    pub fn snap_dir(&self) -> &Path {
+        &self.snap_dir
+    }
+
+    pub fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    pub fn objects(&self) -> &ObjectStore {
+        &self.objects
+    }
+
+    // How many objects `open` moved out of the flat layout
+    pub fn migrated_objects(&self) -> usize {
+        self.migrated_objects
+    }
+
+    pub fn config(&self) -> Config {
+        Config::load(Some(&self.snap_dir))
+    }
+
+    pub(crate) fn snap_path(&self, rel: &str) -> PathBuf {
+        self.snap_dir.join(rel)
+    }
+
+    /// Turns a path given relative to `cwd` into a path relative to the worktree
+    /// root, or None if it lies outside the worktree.
+    pub fn repo_path(&self, cwd: &Path, arg: &str) -> Option<String> {
+        let absolute = normalize_path(&cwd.join(arg));
+        let relative = absolute.strip_prefix(&self.work_tree).ok()?;
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        Some(if relative.is_empty() { ".".to_string() } else { relative })
+    }
+
+    pub fn read_commit(&self, hash: &str) -> Result<Commit> {
+        self.objects.read_commit(hash)
+    }
+
+    pub fn read_tree(&self, hash: &str) -> Result<Tree> {
+        self.objects.read_tree(hash)
+    }
+
+    /// The staged files, without paths a merge left conflicted. A missing INDEX,
+    /// or an empty one written by older versions after every commit, means "same as HEAD".
+    pub fn index(&self) -> Result<Index> {
+        match index::read_index_entries(&self.snap_path("INDEX"))? {
+            Some((staged_files, _)) => Ok(staged_files),
+            None => self.head_tree_files(),
+        }
+    }
+
+    /// The paths a merge left conflicted, with the versions it couldn't reconcile.
+    pub fn conflicts(&self) -> Result<Conflicts> {
+        Ok(index::read_index_entries(&self.snap_path("INDEX"))?.map(|(_, conflicts)| conflicts).unwrap_or_default())
+    }
+
+    /// Writes the staged files. A conflicted path that's staged again counts as
+    /// resolved; other conflicts are kept.
+    pub fn write_index(&self, staged_files: &Index) -> Result<()> {
+        let mut conflicts = self.conflicts()?;
+        conflicts.retain(|path, _| !staged_files.contains_key(path));
+        self.write_index_with_conflicts(staged_files, &conflicts)
+    }
+
+    pub(crate) fn write_index_with_conflicts(&self, staged_files: &Index, conflicts: &Conflicts) -> Result<()> {
+        index::write_index_entries(&self.snap_path("INDEX"), staged_files, conflicts)
+    }
+
+    // Makes the index match a committed tree, e.g. after switching branches
+    pub(crate) fn reset_index(&self, tree_hash: &str) -> Result<()> {
+        let mut files = HashMap::new();
+        self.collect_tree_blobs(tree_hash, "", &mut files)?;
+        self.write_index_with_conflicts(&files, &Conflicts::new())
+    }
+
+    // Every tracked path: the staged files, and each conflicted one as our version
+    // (theirs if we deleted it), which is what its worktree copy started from
+    pub(crate) fn tracked_files(&self) -> Result<Index> {
+        let mut files = self.index()?;
+        for (path, entry) in self.conflicts()? {
+            if let Some(blob_hash) = entry.ours.or(entry.theirs) {
+                files.insert(path, blob_hash);
+            }
+        }
+        Ok(files)
+    }
+
+    fn head_tree_files(&self) -> Result<Index> {
+        let mut files = HashMap::new();
+        let head = self.head()?;
+        if !head.is_empty() {
+            self.collect_tree_blobs(&self.read_commit(&head)?.tree_hash, "", &mut files)?;
+        }
+        Ok(files)
+    }
+
+    /// Every file in a tree as path -> blob hash.
+    pub fn collect_tree_blobs(&self, tree_hash: &str, base_path: &str, files: &mut HashMap<String, String>) -> Result<()> {
+        let tree = self.read_tree(tree_hash)?;
+
+        for entry in tree.entries {
+            let (name, hash, is_dir) = match entry {
+                TreeEntry::File { name, blob_hash } => (name, blob_hash, false),
+                TreeEntry::Directory { name, tree_hash } => (name, tree_hash, true),
+            };
+            let path = if base_path.is_empty() { name } else { format!("{}/{}", base_path, name) };
+            if is_dir {
+                self.collect_tree_blobs(&hash, &path, files)?;
+            } else {
+                files.insert(path, hash);
+            }
+        }
+        Ok(())
+    }
+
+    // Builds nested tree objects from the flat "dir/sub/file" paths in the index.
+    // Identical subdirectories hash to the same tree, so they're shared across commits.
+    pub fn build_tree(&self, files: &Index) -> Result<String> {
+        let mut sorted: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(path, hash)| (path.strip_prefix("./").unwrap_or(path), hash.as_str()))
+            .collect();
+        sorted.sort();
+        self.build_subtree(&sorted, &mut |tree| self.objects.save_tree(&tree))
+    }
+
+    // `files` is sorted by path, so everything under one directory is contiguous.
+    // `save` stores each tree and returns its hash.
+    fn build_subtree(&self, files: &[(&str, &str)], save: &mut dyn FnMut(Tree) -> Result<String>) -> Result<String> {
+        let mut entries = Vec::new();
+        let mut i = 0;
+
+        while i < files.len() {
+            let (path, blob_hash) = files[i];
+            match path.split_once('/') {
+                None => {
+                    entries.push(TreeEntry::File { name: path.to_string(), blob_hash: blob_hash.to_string() });
+                    i += 1;
+                }
+                Some((dir_name, _)) => {
+                    let prefix = format!("{}/", dir_name);
+                    let mut children = Vec::new();
+                    while i < files.len() && files[i].0.starts_with(&prefix) {
+                        children.push((&files[i].0[prefix.len()..], files[i].1));
+                        i += 1;
+                    }
+                    let tree_hash = self.build_subtree(&children, save)?;
+                    entries.push(TreeEntry::Directory { name: dir_name.to_string(), tree_hash });
+                }
+            }
+        }
+
+        save(Tree { entries })
+    }
+
+    /// Stages a file or directory (relative to the worktree root). Tracked files
+    /// under it that are gone from disk are staged as deletions. Conflicts under
+    /// it are resolved as the files now are.
+    pub fn add(&self, path: &str) -> Result<()> {
+        let mut staged_files = self.index()?;
+        let before = staged_files.len();
+
+        // A tracked file that is now a directory is gone too; its new files are staged below
+        staged_files.retain(|file, _| !in_directory(file, path) || self.work_path(file).is_file());
+        let mut conflicts = self.conflicts()?;
+        let conflicted = conflicts.len();
+        conflicts.retain(|file, _| !in_directory(file, path));
+        let removed_or_resolved = staged_files.len() != before || conflicts.len() != conflicted;
+
+        let full_path = self.work_path(path);
+        if full_path.is_file() {
+            let content = fs::read(&full_path).at(&full_path)?;
+            let file_path = path.strip_prefix("./").unwrap_or(path);
+            staged_files.insert(file_path.to_string(), self.objects.save_blob(&content)?);
+        } else if full_path.is_dir() {
+            self.stage_directory(&full_path, &mut staged_files)?;
+        } else if !removed_or_resolved {
+            return Err(SnapError::InvalidArgument(format!("pathspec '{}' did not match any files", path)));
+        }
+
+        self.write_index_with_conflicts(&staged_files, &conflicts)
+    }
+
+    /// Commits the index. `author` ("Name <email>") overrides the configured author.
+    /// Returns the new commit's hash, or None if nothing changed since HEAD.
+    pub fn commit(&self, message: &str, author: Option<&str>) -> Result<Option<String>> {
+        let mut author_sig = Signature::from_env("AUTHOR", now(), &self.config())?;
+        if let Some(identity) = author {
+            let (name, email) = parse_identity(identity)
+                .ok_or_else(|| SnapError::InvalidArgument("--author must look like \"Name <email>\"".to_string()))?;
+            author_sig.name = name;
+            author_sig.email = email;
+        }
+        self.commit_as(message, author_sig)
+    }
+
+    /// Commits the index with a ready-made author, e.g. one recorded in a patch.
+    /// The committer still comes from the environment and config. While a merge
+    /// is in progress, the commit being merged becomes a second parent.
+    pub fn commit_as(&self, message: &str, author_sig: Signature) -> Result<Option<String>> {
+        let committer_sig = Signature::from_env("COMMITTER", now(), &self.config())?;
+
+        let conflicts = self.conflicts()?;
+        if !conflicts.is_empty() {
+            let paths: Vec<&str> = conflicts.keys().map(String::as_str).collect();
+            return Err(SnapError::Conflict(format!(
+                "cannot commit with unmerged paths: {}\nfix them and mark them as resolved with 'add'",
+                paths.join(", ")
+            )));
+        }
+        let staged_files = self.index()?;
+        let tree_hash = self.build_tree(&staged_files)?;
+
+        let parent = self.head()?;
+        let merge_head = self.merge_head()?;
+        let unchanged = if parent.is_empty() { staged_files.is_empty() } else { self.read_commit(&parent)?.tree_hash == tree_hash };
+        // A merge is recorded even when it leaves our tree as it was
+        if unchanged && merge_head.is_none() {
+            return Ok(None);
+        }
+
+        let parents = [parent].into_iter().chain(merge_head).filter(|hash| !hash.is_empty()).collect();
+        let commit = Commit {
+            tree_hash,
+            parents,
+            timestamp: committer_sig.timestamp,
+            message: message.to_string(),
+            author: Some(author_sig),
+            committer: Some(committer_sig),
+        };
+        let commit_hash = self.objects.save_commit(&commit)?;
+        self.update_head(&commit_hash)?;
+        self.clear_merge_state()?;
+
+        // The index keeps the full snapshot so the next commit starts from it
+        self.write_index(&staged_files)?;
+        Ok(Some(commit_hash))
+    }
+
+    /// The commits reachable from HEAD through any parent, newest first, and
+    /// never before a commit that descends from them.
+    pub fn log(&self) -> Result<Vec<(String, Commit)>> {
+        let head = self.head()?;
+        if head.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.walk(vec![head], &HashSet::new())
+    }
+
+    /// The commits reachable from `include` but not from `exclude`, ordered as in
+    /// `log`, as in `exclude..include`.
+    pub fn commits_between(&self, exclude: &str, include: &str) -> Result<Vec<(String, Commit)>> {
+        let excluded = self.reachable(vec![exclude.to_string()])?;
+        self.walk(vec![include.to_string()], &excluded)
+    }
+
+    /// Every commit object in the repository, ordered as in `log`.
+    pub fn log_all(&self) -> Result<Vec<(String, Commit)>> {
+        let mut commits = HashMap::new();
+
+        // Only commit objects are fully decoded; the header tells us the kind
+        for hash in self.objects.list() {
+            if self.objects.read_kind(&hash).ok() == Some(ObjectKind::Commit)
+                && let Ok(commit) = self.read_commit(&hash)
+            {
+                commits.insert(hash, commit);
+            }
+        }
+        Ok(order_commits(commits))
+    }
+
+    // The commits reachable from `starts` that aren't in `excluded`, ordered as in `log`
+    fn walk(&self, starts: Vec<String>, excluded: &HashSet<String>) -> Result<Vec<(String, Commit)>> {
+        let mut commits = HashMap::new();
+        let mut pending = starts;
+        while let Some(hash) = pending.pop() {
+            if hash.is_empty() || excluded.contains(&hash) || commits.contains_key(&hash) {
+                continue;
+            }
+            let commit = self.read_commit(&hash)?;
+            pending.extend(commit.parents.iter().cloned());
+            commits.insert(hash, commit);
+        }
+        Ok(order_commits(commits))
+    }
+
+    /// The file-level changes between two trees, sorted by path, with renames
+    /// detected at the default threshold.
+    pub fn diff_trees(&self, old_tree: &str, new_tree: &str) -> Result<Vec<TreeChange>> {
+        let (old, new) = (Snapshot::Tree(old_tree.to_string()), Snapshot::Tree(new_tree.to_string()));
+        self.diff(&old, &new, &[], &DiffOptions::default())
+    }
+
+    // None stands for a side where the file doesn't exist
+    pub fn diff_blobs(&self, old_hash: Option<&str>, new_hash: Option<&str>, options: &DiffOptions) -> Result<FileDiff> {
+        diff::diff_blobs(&self.objects, old_hash, new_hash, options)
+    }
+
+    /// HEAD's tree, or an empty snapshot before the first commit.
+    pub fn head_snapshot(&self) -> Result<Snapshot> {
+        let head = self.head()?;
+        if head.is_empty() {
+            return Ok(Snapshot::Empty);
+        }
+        Ok(Snapshot::Tree(self.read_commit(&head)?.tree_hash))
+    }
+
+    /// The changes between two snapshots, limited to `paths` (all files if empty)
+    /// and sorted by path. Renames and copies are looked for across the whole
+    /// tree as `options` asks, before the paths are applied, and files that only
+    /// differ in whitespace the options ignore are left out.
+    pub fn diff(&self, old: &Snapshot, new: &Snapshot, paths: &[String], options: &DiffOptions) -> Result<Vec<TreeChange>> {
+        let mut overlay = TreeOverlay::new(&self.objects);
+        let old_tree = self.snapshot_tree(old, &mut overlay)?;
+        let new_tree = self.snapshot_tree(new, &mut overlay)?;
+        let changes = diff::compare_trees_recursive(&overlay, &old_tree, &new_tree, "")?;
+        let mut changes = diff::detect_renames(changes, options, &mut |change, new_side| {
+            if new_side {
+                self.snapshot_content(new, &change.path, change.new_blob())
+            } else {
+                self.snapshot_content(old, &change.path, change.old_blob())
+            }
+        })?;
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut changes = diff::filter_paths(changes, paths);
+
+        // A file whose only changes are whitespace the options ignore isn't modified
+        if options.ignores_whitespace() {
+            let mut kept = Vec::with_capacity(changes.len());
+            for change in changes.drain(..) {
+                if change.kind == diff::ChangeKind::Modified && self.diff_file(&change, old, new, options)? == FileDiff::Text(Vec::new()) {
+                    continue;
+                }
+                kept.push(change);
+            }
+            changes = kept;
+        }
+        Ok(changes)
+    }
+
+    // Index and working tree snapshots become trees in the overlay, not the object store
+    fn snapshot_tree(&self, snapshot: &Snapshot, overlay: &mut TreeOverlay) -> Result<String> {
+        let files = match snapshot {
+            Snapshot::Tree(hash) => return Ok(hash.clone()),
+            Snapshot::Empty => Index::new(),
+            Snapshot::Index => self.tracked_files()?,
+            Snapshot::WorkTree => self.tracked_work_files()?,
+        };
+        let mut sorted: Vec<(&str, &str)> = files.iter().map(|(path, hash)| (path.as_str(), hash.as_str())).collect();
+        sorted.sort();
+        self.build_subtree(&sorted, &mut |tree| Ok(overlay.insert(tree)))
+    }
+
+    /// Diffs one file of a change between `old` and `new`, reading the working
+    /// tree side from disk.
+    pub fn diff_file(&self, change: &TreeChange, old: &Snapshot, new: &Snapshot, options: &DiffOptions) -> Result<FileDiff> {
+        let old_path = change.old_path.as_deref().unwrap_or(&change.path);
+        let old_content = self.snapshot_content(old, old_path, change.old_blob())?;
+        let new_content = self.snapshot_content(new, &change.path, change.new_blob())?;
+        Ok(diff::diff_contents(&old_content, &new_content, options))
+    }
+
+    fn snapshot_content(&self, snapshot: &Snapshot, path: &str, blob: Option<&str>) -> Result<Vec<u8>> {
+        match (blob, snapshot) {
+            (None, _) => Ok(Vec::new()),
+            (Some(_), Snapshot::WorkTree) => {
+                let full_path = self.work_path(path);
+                fs::read(&full_path).at(&full_path)
+            }
+            (Some(hash), _) => self.objects.read_blob(hash),
+        }
+    }
+
+    // Tracked files that still exist on disk, hashed as they are now
+    fn tracked_work_files(&self) -> Result<Index> {
+        let mut files = Index::new();
+        for path in self.tracked_files()?.into_keys() {
+            if let Ok(content) = fs::read(self.work_path(&path)) {
+                files.insert(path, hash_object(&content));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Compares HEAD, the index and the working tree under `directory`.
+    pub fn status(&self, directory: &str) -> Result<Status> {
+        let head_files = self.head_tree_files()?;
+        let staged_files = self.index()?;
+        let conflicts = self.conflicts()?;
+        let working_files = self.scan_directory(directory);
+
+        let mut staged = Vec::new();
+        for (file_path, staged_hash) in &staged_files {
+            match head_files.get(file_path) {
+                None => staged.push((FileStatus::New, file_path.clone())),
+                Some(head_hash) if head_hash != staged_hash => staged.push((FileStatus::Modified, file_path.clone())),
+                Some(_) => {}
+            }
+        }
+        for file_path in head_files.keys() {
+            if !staged_files.contains_key(file_path) && !conflicts.contains_key(file_path) {
+                staged.push((FileStatus::Deleted, file_path.clone()));
+            }
+        }
+        staged.sort_by(|a, b| a.1.cmp(&b.1));
+
+        // limited to the scanned directory
+        let mut unstaged = Vec::new();
+        for (file_path, staged_hash) in &staged_files {
+            if !in_directory(file_path, directory) {
+                continue;
+            }
+            match working_files.get(file_path) {
+                None => unstaged.push((FileStatus::Deleted, file_path.clone())),
+                Some(working_hash) if working_hash != staged_hash => unstaged.push((FileStatus::Modified, file_path.clone())),
+                Some(_) => {}
+            }
+        }
+        unstaged.sort_by(|a, b| a.1.cmp(&b.1));
+
+        let mut untracked: Vec<String> =
+            working_files.into_keys().filter(|path| !staged_files.contains_key(path) && !conflicts.contains_key(path)).collect();
+        untracked.sort();
+
+        let unmerged = conflicts.into_iter().filter(|(path, _)| in_directory(path, directory)).collect();
+        let merging = self.merge_head()?.is_some();
+        let rebasing = self.rebase_in_progress();
+        Ok(Status { branch: self.current_branch()?, head: self.head()?, staged, unstaged, untracked, unmerged, merging, rebasing })
+    }
+
+    /// Detaches HEAD at `revision`, deleting files under `directory` that the
+    /// commit doesn't have and restoring the commit's tree.
+    pub fn checkout_commit(&self, revision: &str, directory: &str) -> Result<Checkout> {
+        let commit_hash = self.resolve_revision(revision)?;
+        let commit = self.read_commit(&commit_hash)?;
+
+        let mut target_files = HashMap::new();
+        self.collect_tree_blobs(&commit.tree_hash, "", &mut target_files)?;
+
+        let mut checkout = Checkout { commit: commit_hash.clone(), ..Checkout::default() };
+        let current_files: HashSet<String> = self.list_files(directory);
+        for file in current_files {
+            if target_files.contains_key(&file) {
+                continue;
+            }
+            match fs::remove_file(self.work_path(&file)) {
+                Ok(_) => checkout.deleted.push(file),
+                Err(e) => checkout.failed.push((file, e.to_string())),
+            }
+        }
+
+        self.restore_tree(&commit.tree_hash, "", &mut checkout.restored)?;
+        self.reset_index(&commit.tree_hash)?;
+        self.detach_head(&commit_hash)?;
+        Ok(checkout)
+    }
+
+    /// Switches HEAD to `branch`, replacing everything under `directory` with the
+    /// branch's tree.
+    pub fn checkout_branch(&self, branch: &str, directory: &str) -> Result<Checkout> {
+        let commit_hash = self.branch_commit(branch)?.ok_or_else(|| SnapError::BadRevision(branch.to_string()))?;
+        let commit = self.read_commit(&commit_hash)?;
+
+        self.clear_directory(directory);
+
+        let mut checkout = Checkout { commit: commit_hash, ..Checkout::default() };
+        self.restore_tree(&commit.tree_hash, "", &mut checkout.restored)?;
+        self.reset_index(&commit.tree_hash)?;
+        self.set_head_branch(branch)?;
+        Ok(checkout)
+    }
+
+    /// Writes our or their version of every conflicted file under `path` to the
+    /// worktree, as a starting point for resolving it. The conflict stays until
+    /// the file is staged with `add`.
+    pub fn checkout_conflict_side(&self, path: &str, stage: Stage) -> Result<Vec<String>> {
+        let mut versions = Vec::new();
+        for (file_path, entry) in self.conflicts()?.iter().filter(|(file_path, _)| in_directory(file_path, path)) {
+            let side = if stage == Stage::Theirs { "their" } else { "our" };
+            let blob_hash = entry.version(stage).ok_or_else(|| SnapError::Conflict(format!("'{}' does not have {} version", file_path, side)))?;
+            versions.push((file_path.clone(), blob_hash.to_string()));
+        }
+        if versions.is_empty() {
+            return Err(SnapError::InvalidArgument(format!("pathspec '{}' did not match any conflicted files", path)));
+        }
+
+        for (file_path, blob_hash) in &versions {
+            self.write_work_file(file_path, &self.objects.read_blob(blob_hash)?)?;
+        }
+        Ok(versions.into_iter().map(|(file_path, _)| file_path).collect())
+    }
+}
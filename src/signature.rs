@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{Result, SnapError};
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    // Minutes east of UTC
+    pub tz_offset: i32,
+}
+
+impl Signature {
+    // Builds an author or committer signature: SNAP_<ROLE>_* env vars, then config, then $USER
+    pub fn from_env(role: &str, now: i64, config: &Config) -> Result<Signature> {
+        let env = |field: &str| std::env::var(format!("SNAP_{}_{}", role, field)).ok();
+        let fallback_name = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_string());
+
+        let name = env("NAME").or_else(|| config.get("user.name")).unwrap_or_else(|| fallback_name.clone());
+        let email = env("EMAIL").or_else(|| config.get("user.email")).unwrap_or_else(|| format!("{}@localhost", fallback_name));
+        let (timestamp, tz_offset) = match env("DATE") {
+            Some(date) => parse_date(&date)?,
+            None => (now, local_tz_offset(now)),
+        };
+
+        Ok(Signature { name, email, timestamp, tz_offset })
+    }
+}
+
+// The local timezone's offset from UTC at `timestamp`, in minutes east
+#[cfg(unix)]
+pub fn local_tz_offset(timestamp: i64) -> i32 {
+    let time = timestamp as libc::time_t;
+    // SAFETY: localtime_r only writes to the zeroed struct we hand it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let converted = unsafe { libc::localtime_r(&time, &mut tm) };
+    if converted.is_null() { 0 } else { (tm.tm_gmtoff / 60) as i32 }
+}
+
+#[cfg(not(unix))]
+pub fn local_tz_offset(_timestamp: i64) -> i32 {
+    0
+}
+
+// Parses "Name <email>" as given to `commit --author`
+pub fn parse_identity(identity: &str) -> Option<(String, String)> {
+    let (name, rest) = identity.split_once('<')?;
+    let email = rest.strip_suffix('>')?;
+    Some((name.trim().to_string(), email.trim().to_string()))
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// Accepts "<unix seconds>", "<unix seconds> <+hhmm>", "YYYY-MM-DD HH:MM:SS <+hhmm>" as
+// written by `format_date`, or "[Thu, ]17 Oct 2024 10:00:00 +0200" as in mail headers
+pub fn parse_date(date: &str) -> Result<(i64, i32)> {
+    let invalid = || SnapError::InvalidArgument(format!("invalid date '{}'", date));
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let parsed = match parts[..] {
+        [day, time, tz] if day.contains('-') => parse_civil(day, time, tz),
+        [weekday, day, month, year, time, tz] if WEEKDAYS.iter().any(|name| weekday.strip_suffix(',') == Some(name)) => {
+            parse_rfc2822(day, month, year, time, tz)
+        }
+        [day, month, year, time, tz] => parse_rfc2822(day, month, year, time, tz),
+        [timestamp] => timestamp.parse().ok().map(|timestamp| (timestamp, 0)),
+        [timestamp, tz] => timestamp.parse().ok().zip(parse_tz_offset(tz)),
+        _ => None,
+    };
+    parsed.ok_or_else(invalid)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_civil(day: &str, time: &str, tz: &str) -> Option<(i64, i32)> {
+    let numbers = |text: &str, sep: char| -> Option<Vec<i64>> { text.split(sep).map(|n| n.parse().ok()).collect() };
+    let (day, time) = (numbers(day, '-')?, numbers(time, ':')?);
+    let (&[year, month, day], &[hours, minutes, seconds]) = (day.as_slice(), time.as_slice()) else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) || !(0..60).contains(&seconds) {
+        return None;
+    }
+    let tz_offset = parse_tz_offset(tz)?;
+
+    // Days-from-civil, the inverse of the conversion in `format_date`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let local = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Some((local - tz_offset as i64 * 60, tz_offset))
+}
+
+// The day, month name and year of a mail date, checked by `parse_civil`
+fn parse_rfc2822(day: &str, month: &str, year: &str, time: &str, tz: &str) -> Option<(i64, i32)> {
+    let month = MONTHS.iter().position(|name| name.eq_ignore_ascii_case(month))? + 1;
+    if !day.bytes().all(|b| b.is_ascii_digit()) || !year.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    parse_civil(&format!("{}-{}-{}", year, month, day), time, tz)
+}
+
+fn parse_tz_offset(tz: &str) -> Option<i32> {
+    let (sign, digits) = match tz.as_bytes().first()? {
+        b'+' => (1, &tz[1..]),
+        b'-' => (-1, &tz[1..]),
+        _ => return None,
+    };
+    // Mail headers can hold anything; only ASCII digits are safe to slice by byte
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    // Real offsets lie within 14 hours of UTC
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+// The local date at `timestamp` as (days since 1970-01-01, year, month, day, seconds into the day)
+fn civil_from_timestamp(timestamp: i64, tz_offset: i32) -> (i64, i64, i64, i64, i64) {
+    let local = timestamp + tz_offset as i64 * 60;
+    let days = local.div_euclid(86400);
+    let secs = local.rem_euclid(86400);
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (days, year, month, day, secs)
+}
+
+fn format_tz_offset(tz_offset: i32) -> String {
+    let sign = if tz_offset < 0 { '-' } else { '+' };
+    let offset = tz_offset.abs();
+    format!("{}{:02}{:02}", sign, offset / 60, offset % 60)
+}
+
+// Formats a signature's time as "YYYY-MM-DD HH:MM:SS +hhmm" in its own timezone
+pub fn format_date(timestamp: i64, tz_offset: i32) -> String {
+    let (_, year, month, day, secs) = civil_from_timestamp(timestamp, tz_offset);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+        year, month, day, secs / 3600, secs % 3600 / 60, secs % 60, format_tz_offset(tz_offset)
+    )
+}
+
+// Formats a signature's time for a mail header (RFC 2822): "Thu, 17 Oct 2024 10:00:00 +0200"
+pub fn format_rfc2822_date(timestamp: i64, tz_offset: i32) -> String {
+    let (days, year, month, day, secs) = civil_from_timestamp(timestamp, tz_offset);
+    // 1970-01-01 was a Thursday
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    format!(
+        "{}, {} {} {:04} {:02}:{:02}:{:02} {}",
+        weekday, day, MONTHS[month as usize - 1], year, secs / 3600, secs % 3600 / 60, secs % 60, format_tz_offset(tz_offset)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_round_trip_with_their_offset() {
+        for (timestamp, tz_offset) in [(0, 0), (1_700_000_000, 330), (1_700_000_000, -480), (951_782_400, 60)] {
+            assert_eq!(parse_date(&format_date(timestamp, tz_offset)).unwrap(), (timestamp, tz_offset));
+        }
+        assert_eq!(parse_date("1700000000 -0230").unwrap(), (1_700_000_000, -150));
+        assert_eq!(parse_date("2024-02-29 23:59:59 +1400").unwrap(), (1_709_200_799, 840));
+    }
+
+    #[test]
+    fn mail_dates_round_trip() {
+        assert_eq!(format_rfc2822_date(1_729_152_000, 120), "Thu, 17 Oct 2024 10:00:00 +0200");
+        assert_eq!(format_rfc2822_date(0, 0), "Thu, 1 Jan 1970 00:00:00 +0000");
+        assert_eq!(format_rfc2822_date(-1, -60), "Wed, 31 Dec 1969 22:59:59 -0100");
+        for (timestamp, tz_offset) in [(0, 0), (1_729_152_000, 120), (1_700_000_000, -480), (951_782_400, 330)] {
+            assert_eq!(parse_date(&format_rfc2822_date(timestamp, tz_offset)).unwrap(), (timestamp, tz_offset));
+        }
+        assert_eq!(parse_date("17 oct 2024 10:00:00 +0200").unwrap(), (1_729_152_000, 120));
+        for date in ["Thu 17 Oct 2024 10:00:00 +0200", "17 Oct 2024 10:00", "31 Sep 2024 10:00:00 +0000", "17 Okt 2024 10:00:00 +0000", "+1 Oct 2024 10:00:00 +0000"] {
+            assert!(parse_date(date).is_err(), "{:?}", date);
+        }
+    }
+
+    #[test]
+    fn bad_offsets_are_rejected_without_panicking() {
+        for tz in ["+05", "+0a30", "+0é3", "+é30", "-\u{1F600}", "0530", "+"] {
+            assert_eq!(parse_tz_offset(tz), None, "{:?}", tz);
+        }
+        assert!(parse_date("2024-01-01 10:00:00 +ü00").is_err());
+    }
+
+    #[test]
+    fn out_of_range_fields_are_rejected() {
+        for date in [
+            "2024-01-01 99:99:99 +9999",
+            "2024-01-01 24:00:00 +0000",
+            "2024-01-01 10:60:00 +0000",
+            "2024-01-01 10:00:60 +0000",
+            "2024-01-01 -1:00:00 +0000",
+            "2024-01-01 10:00:00 +0060",
+            "2024-01-01 10:00:00 +1500",
+            "2023-02-29 10:00:00 +0000",
+            "2024-04-31 10:00:00 +0000",
+            "1700000000 +2500",
+            "1700000000 +0000 extra",
+            "",
+        ] {
+            assert!(matches!(parse_date(date), Err(SnapError::InvalidArgument(_))), "{:?}", date);
+        }
+    }
+}
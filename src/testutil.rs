@@ -0,0 +1,73 @@
+use std::{fs, ops::Deref, path::PathBuf};
+
+use crate::repository::Repository;
+
+// A repository in a scratch directory for tests, removed again when dropped
+pub(crate) struct TestRepo {
+    dir: PathBuf,
+    repo: Repository,
+}
+
+impl TestRepo {
+    pub(crate) fn new(name: &str) -> TestRepo {
+        let dir = std::env::temp_dir().join(format!("snap-test-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir, None).unwrap();
+        TestRepo { dir, repo }
+    }
+
+    pub(crate) fn write(&self, path: &str, content: &str) {
+        let full_path = self.dir.join(path);
+        fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        fs::write(full_path, content).unwrap();
+    }
+
+    // A file or a whole directory
+    pub(crate) fn remove(&self, path: &str) {
+        let full_path = self.dir.join(path);
+        if full_path.is_dir() {
+            fs::remove_dir_all(full_path).unwrap();
+        } else {
+            fs::remove_file(full_path).unwrap();
+        }
+    }
+
+    pub(crate) fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join(path)).ok()
+    }
+
+    // Stages everything and commits it
+    pub(crate) fn commit_all(&self, message: &str) -> String {
+        self.repo.add(".").unwrap();
+        self.repo.commit(message, None).unwrap().expect("something to commit")
+    }
+
+    // Creates `branch` at HEAD and switches to it
+    pub(crate) fn branch(&self, branch: &str) {
+        self.repo.create_branch(branch).unwrap();
+        self.switch(branch);
+    }
+
+    pub(crate) fn switch(&self, branch: &str) {
+        self.repo.checkout_branch(branch, ".").unwrap();
+    }
+
+    pub(crate) fn current_branch(&self) -> String {
+        self.repo.current_branch().unwrap().expect("on a branch")
+    }
+}
+
+impl Deref for TestRepo {
+    type Target = Repository;
+
+    fn deref(&self) -> &Repository {
+        &self.repo
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{IoContext, Result};
+use crate::object::{hash_object, TreeEntry};
+use crate::repository::Repository;
+
+// Walks of the working tree. Paths handed in and out are relative to the
+// worktree root, with "." meaning the root itself.
+impl Repository {
+    pub(crate) fn work_path(&self, rel: &str) -> PathBuf {
+        match rel.strip_prefix("./").unwrap_or(rel) {
+            "" | "." => self.work_tree().to_path_buf(),
+            rel => self.work_tree().join(rel),
+        }
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(self.work_tree()).unwrap_or(path);
+        relative.to_string_lossy().replace('\\', "/")
+    }
+
+    fn is_snap_dir(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| name == ".snap") || path == self.snap_dir()
+    }
+
+    pub(crate) fn stage_directory(&self, dir: &Path, staged_files: &mut HashMap<String, String>) -> Result<()> {
+        for entry in fs::read_dir(dir).at(dir)? {
+            let path = entry.at(dir)?.path();
+
+            if self.is_snap_dir(&path) {
+                continue;
+            }
+
+            if path.is_file() {
+                let content = fs::read(&path).at(&path)?;
+                let blob_hash = self.objects().save_blob(&content)?;
+                staged_files.insert(self.relative(&path), blob_hash);
+            } else if path.is_dir() {
+                self.stage_directory(&path, staged_files)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Hashes every file under `dir` without writing any objects
+    pub(crate) fn scan_directory(&self, dir: &str) -> HashMap<String, String> {
+        let mut files = HashMap::new();
+        self.scan(&self.work_path(dir), &mut files);
+        files
+    }
+
+    fn scan(&self, dir: &Path, files: &mut HashMap<String, String>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if self.is_snap_dir(&path) {
+                continue;
+            }
+
+            if path.is_file() {
+                let Ok(content) = fs::read(&path) else {
+                    continue;
+                };
+                files.insert(self.relative(&path), hash_object(&content));
+            } else if path.is_dir() {
+                self.scan(&path, files);
+            }
+        }
+    }
+
+    pub(crate) fn list_files(&self, dir: &str) -> HashSet<String> {
+        self.scan_directory_names(&self.work_path(dir))
+    }
+
+    fn scan_directory_names(&self, dir: &Path) -> HashSet<String> {
+        let mut files = HashSet::new();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return files;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if self.is_snap_dir(&path) {
+                continue;
+            }
+
+            if path.is_file() {
+                files.insert(self.relative(&path));
+            } else if path.is_dir() {
+                files.extend(self.scan_directory_names(&path));
+            }
+        }
+        files
+    }
+
+    // Writes every file of the tree into the worktree, recording the paths written
+    pub(crate) fn restore_tree(&self, tree_hash: &str, base_path: &str, restored: &mut Vec<String>) -> Result<()> {
+        let tree = self.read_tree(tree_hash)?;
+
+        for entry in tree.entries {
+            match entry {
+                TreeEntry::File { name, blob_hash } => {
+                    let content = self.objects().read_blob(&blob_hash)?;
+                    let file_path = if base_path.is_empty() { name } else { format!("{}/{}", base_path, name) };
+
+                    let full_path = self.work_path(&file_path);
+                    if let Some(parent) = full_path.parent() {
+                        fs::create_dir_all(parent).at(parent)?;
+                    }
+                    fs::write(&full_path, content).at(&full_path)?;
+                    restored.push(file_path);
+                }
+                TreeEntry::Directory { name, tree_hash } => {
+                    let dir_path = if base_path.is_empty() { name } else { format!("{}/{}", base_path, name) };
+                    let full_path = self.work_path(&dir_path);
+                    fs::create_dir_all(&full_path).at(&full_path)?;
+                    self.restore_tree(&tree_hash, &dir_path, restored)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_work_file(&self, rel: &str, content: &[u8]) -> Result<()> {
+        let full_path = self.work_path(rel);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).at(parent)?;
+        }
+        fs::write(&full_path, content).at(&full_path)
+    }
+
+    // Deletes a file and any directories it leaves empty; a file that's already gone is fine
+    pub(crate) fn remove_work_file(&self, rel: &str) -> Result<()> {
+        let full_path = self.work_path(rel);
+        match fs::remove_file(&full_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).at(&full_path),
+        }
+        let mut dir = full_path.parent();
+        while let Some(parent) = dir {
+            if parent == self.work_tree() || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+
+    // Removes everything under `dir` except the repository itself
+    pub(crate) fn clear_directory(&self, dir: &str) {
+        self.clear(&self.work_path(dir));
+    }
+
+    fn clear(&self, dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if self.is_snap_dir(&path) {
+                continue;
+            }
+
+            if path.is_file() {
+                fs::remove_file(path).ok();
+            } else if path.is_dir() {
+                self.clear(&path);
+                fs::remove_dir(path).ok(); // Remove empty directory
+            }
+        }
+    }
+}